@@ -0,0 +1,30 @@
+/* the parts of the CUDA runtime headers generated code relies on, enough
+ * for `clang -x cuda -nocudainc -nocudalib -fsyntax-only` without a CUDA
+ * installation */
+#include <stddef.h>
+
+#define __host__ __attribute__((host))
+#define __device__ __attribute__((device))
+#define __global__ __attribute__((global))
+#define __shared__ __attribute__((shared))
+#define __constant__ __attribute__((constant))
+
+struct dim3 {
+    unsigned int x, y, z;
+    __host__ __device__ dim3(unsigned int x = 1, unsigned int y = 1, unsigned int z = 1)
+        : x(x), y(y), z(z) {}
+};
+struct uint3 {
+    unsigned int x, y, z;
+};
+
+typedef struct CUstream_st *cudaStream_t;
+
+/* the launch configuration call `<<<...>>>` lowers to, by CUDA version */
+extern "C" int cudaConfigureCall(dim3 grid, dim3 block, size_t shared = 0,
+                                 cudaStream_t stream = 0);
+extern "C" unsigned __cudaPushCallConfiguration(dim3 grid, dim3 block,
+                                                size_t shared = 0,
+                                                cudaStream_t stream = 0);
+
+#include <__clang_cuda_builtin_vars.h>
@@ -0,0 +1,76 @@
+//! prints a CUDA translation unit using the CUDA extensions of the
+//! generator, CI checks it with `clang -x cuda -fsyntax-only`
+
+#![allow(clippy::result_large_err)]
+
+use s2c::c_cg::{
+    c_cuda::{Axis, FunctionSpace, ThreadIndex},
+    c_error::CResult,
+    c_stmt::Context,
+    c_type::{CType, CudaType},
+    c_value::{BinaryOp, CLiteral, CValue, IntegerSuffix, Radix},
+};
+
+fn int(v: i128) -> CValue {
+    CValue::Literal(CLiteral::Int(v, IntegerSuffix::None, Radix::Decimal))
+}
+
+fn main() -> CResult<()> {
+    let context = Context::cuda("scale".to_string());
+    let f32_ptr = CType::Pointer {
+        ty: Box::new(CType::F32),
+    };
+    context
+        .constant(CType::F32, "factor".to_string(), Some(int(2)))?
+        .def_cuda(
+            FunctionSpace::Global,
+            "scale".to_string(),
+            CType::Void,
+            vec![(f32_ptr.clone(), "xs".to_string())],
+            |c| {
+                c.shared(
+                    CType::Array {
+                        ty: Box::new(CType::F32),
+                        size: Some(256),
+                    },
+                    "tile".to_string(),
+                )?
+                .set(
+                    CType::U32,
+                    "i".to_string(),
+                    CValue::BinOp(
+                        BinaryOp::Add,
+                        Box::new(CValue::BinOp(
+                            BinaryOp::Mul,
+                            Box::new(ThreadIndex::BlockIdx.get(Axis::X)),
+                            Box::new(ThreadIndex::BlockDim.get(Axis::X)),
+                        )),
+                        Box::new(ThreadIndex::ThreadIdx.get(Axis::X)),
+                    ),
+                )?;
+                Ok(c)
+            },
+        )?
+        .def(
+            "run".to_string(),
+            CType::Void,
+            vec![(f32_ptr, "xs".to_string())],
+            |c| {
+                c.set(
+                    CType::CudaExtension(CudaType::Dim3),
+                    "grid".to_string(),
+                    int(4),
+                )?
+                .launch(
+                    "scale".to_string(),
+                    CValue::Variable("grid".to_string()),
+                    int(256),
+                    None,
+                    vec![CValue::Variable("xs".to_string())],
+                )?;
+                Ok(c)
+            },
+        )?;
+    print!("{}", context.render());
+    Ok(())
+}
@@ -1,4 +1,38 @@
-use super::{ToC, c_stmt::Context};
+use super::{
+    ToC,
+    c_error::{CResult, CodegenError},
+    c_stmt::Context,
+};
+
+/// sizes of the builtin integer types, used instead of `<stdint.h>` on
+/// targets that do not provide it
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum DataModel {
+    /// 64-bit `long` and pointers, posix 64-bit targets
+    LP64,
+    /// 32-bit `long`, 64-bit `long long` and pointers, 64-bit windows
+    LLP64,
+    /// 32-bit `int`, `long` and pointers
+    ILP32,
+}
+
+impl DataModel {
+    /// the builtin type spelling the integer of `bits` width
+    pub fn integer(&self, bits: usize, signed: bool) -> &'static str {
+        match (bits, signed, self) {
+            (8, true, _) => "signed char",
+            (8, false, _) => "unsigned char",
+            (16, true, _) => "signed short int",
+            (16, false, _) => "unsigned short int",
+            (32, true, _) => "signed int",
+            (32, false, _) => "unsigned int",
+            (_, true, DataModel::LP64) => "signed long int",
+            (_, false, DataModel::LP64) => "unsigned long int",
+            (_, true, _) => "signed long long int",
+            (_, false, _) => "unsigned long long int",
+        }
+    }
+}
 
 /// now we are not considering embedded systems
 pub enum Arch {
@@ -16,26 +50,29 @@ pub enum Arch {
 }
 
 impl ToC for Arch {
-    fn to_c(&self, dialect: super::CDialect, _context: &Context) -> Option<String> {
-        assert!(matches!(dialect, super::CDialect::Standard));
+    fn to_c(&self, dialect: super::CDialect, _context: &Context) -> CResult<String> {
+        if dialect != super::CDialect::Standard {
+            return Err(CodegenError::UnsupportedFeature {
+                feature: "architecture detection",
+                dialect,
+            });
+        }
         match self {
             Arch::WindowsX86 => {
-                Some("defined(_WIN32) && defined(_M_IX86) || defined(__i386__)".to_string())
+                Ok("defined(_WIN32) && defined(_M_IX86) || defined(__i386__)".to_string())
             }
             Arch::WindowsX86_64 => {
-                Some("defined(_WIN32) && defined(_M_X64) || defined(__x86_64__)".to_string())
+                Ok("defined(_WIN32) && defined(_M_X64) || defined(__x86_64__)".to_string())
             }
             Arch::WindowsAArch64 => {
-                Some("defined(_WIN32) && defined(_M_ARM64) || defined(__aarch64__)".to_string())
-            }
-            Arch::PosixX86_64 => Some("defined(__unix__) && defined(__x86_64__)".to_string()),
-            Arch::PosixAArch64 => Some("defined(__unix__) && defined(__aarch64__)".to_string()),
-            Arch::PosixRiscv64GC => Some("defined(__unix__) && defined(__riscv)".to_string()),
-            Arch::Posixloongarch64 => {
-                Some("defined(__unix__) && defined(__loongarch64)".to_string())
+                Ok("defined(_WIN32) && defined(_M_ARM64) || defined(__aarch64__)".to_string())
             }
+            Arch::PosixX86_64 => Ok("defined(__unix__) && defined(__x86_64__)".to_string()),
+            Arch::PosixAArch64 => Ok("defined(__unix__) && defined(__aarch64__)".to_string()),
+            Arch::PosixRiscv64GC => Ok("defined(__unix__) && defined(__riscv)".to_string()),
+            Arch::Posixloongarch64 => Ok("defined(__unix__) && defined(__loongarch64)".to_string()),
             Arch::EmscriptenWasm32 => {
-                Some("defined(__EMSCRIPTEN__) && defined(__wasm32__)".to_string())
+                Ok("defined(__EMSCRIPTEN__) && defined(__wasm32__)".to_string())
             }
         }
     }
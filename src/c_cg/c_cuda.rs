@@ -0,0 +1,402 @@
+use std::sync::{Arc, Mutex};
+
+use super::{
+    CDialect, KernelTarget, ToC,
+    c_error::{CResult, CodegenError},
+    c_file::{CFile, CFunction, Visibility},
+    c_stmt::{Context, Variable},
+    c_type::{CType, CudaType},
+    c_value::CValue,
+};
+
+const CUDA: CDialect = CDialect::Kernel(KernelTarget::Cuda);
+
+/// CUDA spelling of `ty`, only available in the CUDA dialect
+pub(crate) fn cuda_type(ty: &CudaType, dialect: CDialect) -> CResult<String> {
+    let unsupported = || CodegenError::UnsupportedType {
+        ty: CType::CudaExtension(ty.clone()),
+        dialect,
+    };
+    if dialect != CUDA {
+        return Err(unsupported());
+    }
+    match ty {
+        CudaType::Dim3 => Ok("dim3".to_string()),
+        CudaType::Vector { ty: element, size } => {
+            let element = match element.as_ref() {
+                CType::I8 => "char",
+                CType::U8 => "uchar",
+                CType::I16 => "short",
+                CType::U16 => "ushort",
+                CType::I32 => "int",
+                CType::U32 => "uint",
+                CType::I64 => "longlong",
+                CType::U64 => "ulonglong",
+                CType::F32 => "float",
+                CType::F64 => "double",
+                _ => return Err(unsupported()),
+            };
+            if !(1..=4).contains(size) {
+                return Err(unsupported());
+            }
+            Ok(format!("{}{}", element, size))
+        }
+    }
+}
+
+/// where a CUDA function runs and where it can be called from
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum FunctionSpace {
+    /// a kernel, runs on the device and is launched from the host
+    Global,
+    Device,
+    Host,
+    /// compiled for both sides
+    HostDevice,
+}
+
+impl FunctionSpace {
+    fn qualifiers(self) -> &'static str {
+        match self {
+            FunctionSpace::Global => "__global__",
+            FunctionSpace::Device => "__device__",
+            FunctionSpace::Host => "__host__",
+            FunctionSpace::HostDevice => "__host__ __device__",
+        }
+    }
+}
+
+/// built-in variables of a kernel, each a `dim3` (`uint3`)
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ThreadIndex {
+    ThreadIdx,
+    BlockIdx,
+    BlockDim,
+    GridDim,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Axis {
+    X,
+    Y,
+    Z,
+}
+
+impl ThreadIndex {
+    /// `threadIdx.x`, ...
+    pub fn get(self, axis: Axis) -> CValue {
+        let name = match self {
+            ThreadIndex::ThreadIdx => "threadIdx",
+            ThreadIndex::BlockIdx => "blockIdx",
+            ThreadIndex::BlockDim => "blockDim",
+            ThreadIndex::GridDim => "gridDim",
+        };
+        let axis = match axis {
+            Axis::X => "x",
+            Axis::Y => "y",
+            Axis::Z => "z",
+        };
+        CValue::MemberAccess(Box::new(CValue::Extern(name.to_string())), axis.to_string())
+    }
+}
+
+impl Context {
+    /// a context generating a CUDA `.cu` file
+    pub fn cuda(module_name: String) -> Self {
+        let c_file = CFile {
+            dialect: CUDA,
+            ..Default::default()
+        };
+        Self {
+            c_file: Arc::new(Mutex::new(c_file)),
+            module: module_name,
+            dialect: CUDA,
+            variables: Default::default(),
+            current_source: Default::default(),
+            last_statement: Default::default(),
+            scopes: Default::default(),
+            jumps: Default::default(),
+            device: false,
+        }
+    }
+
+    fn check_cuda(&self, feature: &'static str) -> CResult<()> {
+        if self.dialect != CUDA {
+            return Err(CodegenError::UnsupportedFeature {
+                feature,
+                dialect: self.dialect,
+            });
+        }
+        Ok(())
+    }
+
+    /// define a function qualified with `space`, kernels must return `void`
+    pub fn def_cuda(
+        &self,
+        space: FunctionSpace,
+        name: Variable,
+        ret: CType,
+        args: Vec<(CType, Variable)>,
+        body: impl Fn(Self) -> CResult<Self>,
+    ) -> CResult<&Self> {
+        self.check_cuda("function space qualifiers")?;
+        if space == FunctionSpace::Global && ret != CType::Void {
+            return Err(CodegenError::UnsupportedType {
+                ty: ret,
+                dialect: self.dialect,
+            });
+        }
+        let name = self.c_name(&name);
+        let device = matches!(space, FunctionSpace::Global | FunctionSpace::Device);
+        let arity = args.len();
+        let (prototype, body) = self.function(&name, ret, args, |mut c| {
+            c.device = device;
+            body(c)
+        })?;
+        let mut c_file = self.c_file.lock().unwrap();
+        if space == FunctionSpace::Global {
+            c_file.kernels.insert(name, arity);
+        }
+        c_file.functions.push(CFunction {
+            prototype: format!("{} {}", space.qualifiers(), prototype),
+            body,
+            visibility: Visibility::Exported,
+            entry_point: false,
+        });
+        Ok(self)
+    }
+
+    /// `__shared__ ty name;`, memory shared by the threads of a block,
+    /// declared in the current `__global__` or `__device__` function
+    pub fn shared(&self, ty: CType, name: Variable) -> CResult<&Self> {
+        self.check_cuda("shared memory")?;
+        if !self.device {
+            return Err(CodegenError::OutsideDeviceCode("shared memory"));
+        }
+        let declaration = ty.declare(&self.c_name(&name), self.dialect, self)?;
+        self.variables.lock().unwrap().insert(name, ty);
+        self.current_source
+            .lock()
+            .unwrap()
+            .push_str(&format!("__shared__ {};\n", declaration));
+        Ok(self)
+    }
+
+    /// `__constant__ ty name = value;`, read-only device memory visible to
+    /// every function, the host fills it with `cudaMemcpyToSymbol`
+    pub fn constant(&self, ty: CType, name: Variable, value: Option<CValue>) -> CResult<&Self> {
+        self.check_cuda("constant memory")?;
+        let declaration = ty.declare(&self.c_name(&name), self.dialect, self)?;
+        let init = match value {
+            Some(value) => format!(" = {}", value.to_c(self.dialect, self)?),
+            None => "".to_string(),
+        };
+        self.variables.lock().unwrap().insert(name, ty);
+        self.global_inline_c(format!("__constant__ {}{};", declaration, init));
+        Ok(self)
+    }
+
+    /// `kernel<<<grid, block, shared>>>(args);`, `grid` and `block` are
+    /// `dim3` or integer values, `shared` the bytes of dynamic shared memory;
+    /// `kernel` has to be defined before with `def_cuda(FunctionSpace::Global, ..)`
+    pub fn launch(
+        &self,
+        kernel: Variable,
+        grid: CValue,
+        block: CValue,
+        shared: Option<CValue>,
+        args: Vec<CValue>,
+    ) -> CResult<&Self> {
+        self.check_cuda("kernel launches")?;
+        let c_name = self.c_name(&kernel);
+        let expected = self.c_file.lock().unwrap().kernels.get(&c_name).copied();
+        match expected {
+            None => return Err(CodegenError::NotAKernel(kernel)),
+            Some(expected) if expected != args.len() => {
+                return Err(CodegenError::ArgumentCount {
+                    function: kernel,
+                    expected,
+                    found: args.len(),
+                });
+            }
+            Some(_) => {}
+        }
+        let mut config = vec![
+            grid.to_c(self.dialect, self)?,
+            block.to_c(self.dialect, self)?,
+        ];
+        if let Some(shared) = shared {
+            config.push(shared.to_c(self.dialect, self)?);
+        }
+        let args = args
+            .iter()
+            .map(|arg| arg.to_c(self.dialect, self))
+            .collect::<CResult<Vec<_>>>()?
+            .join(", ");
+        let code = format!("{}<<<{}>>>({});\n", c_name, config.join(", "), args);
+        self.current_source.lock().unwrap().push_str(&code);
+        Ok(self)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::c_cg::c_value::{CLiteral, IntegerSuffix, Radix};
+
+    #[test]
+    fn test_cuda_types() {
+        let context = Context::cuda("m".to_string());
+        let vector = |ty, size| {
+            CType::CudaExtension(CudaType::Vector {
+                ty: Box::new(ty),
+                size,
+            })
+        };
+        assert_eq!(
+            vector(CType::F32, 4).to_c(CUDA, &context).unwrap(),
+            "float4"
+        );
+        assert!(vector(CType::F32, 8).to_c(CUDA, &context).is_err());
+        let bits = CType::Union {
+            name: Some("bits".to_string()),
+            fields: vec![("f".to_string(), CType::F32), ("u".to_string(), CType::U32)],
+        };
+        assert_eq!(bits.to_c(CUDA, &context).unwrap(), "union m_MM_bits");
+        assert!(
+            CType::CudaExtension(CudaType::Dim3)
+                .to_c(CDialect::Standard, &context)
+                .is_err()
+        );
+    }
+
+    #[test]
+    fn test_cuda_kernel_launch() {
+        let context = Context::cuda("m".to_string());
+        let int = |v| CValue::Literal(CLiteral::Int(v, IntegerSuffix::None, Radix::Decimal));
+        let f32_ptr = CType::Pointer {
+            ty: Box::new(CType::F32),
+        };
+        context
+            .constant(CType::F32, "k".to_string(), Some(int(2)))
+            .unwrap()
+            .def_cuda(
+                FunctionSpace::Global,
+                "scale".to_string(),
+                CType::Void,
+                vec![(f32_ptr.clone(), "xs".to_string())],
+                |c| {
+                    c.shared(
+                        CType::Array {
+                            ty: Box::new(CType::F32),
+                            size: Some(256),
+                        },
+                        "tile".to_string(),
+                    )?;
+                    c.set(
+                        CType::U32,
+                        "i".to_string(),
+                        ThreadIndex::ThreadIdx.get(Axis::X),
+                    )?;
+                    Ok(c)
+                },
+            )
+            .unwrap()
+            .def(
+                "run".to_string(),
+                CType::Void,
+                vec![(f32_ptr, "xs".to_string())],
+                |c| {
+                    c.launch(
+                        "scale".to_string(),
+                        int(4),
+                        int(256),
+                        None,
+                        vec![CValue::Variable("xs".to_string())],
+                    )?;
+                    Ok(c)
+                },
+            )
+            .unwrap();
+        // only kernels defined so far launch, with all their arguments
+        let launch = |kernel: &str, args: usize| {
+            context
+                .def("l".to_string(), CType::Void, vec![], |c| {
+                    c.launch(
+                        kernel.to_string(),
+                        int(1),
+                        int(1),
+                        None,
+                        (0..args).map(|_| int(0)).collect(),
+                    )?;
+                    Ok(c)
+                })
+                .err()
+        };
+        assert_eq!(
+            launch("run", 1),
+            Some(CodegenError::NotAKernel("run".to_string()))
+        );
+        assert_eq!(
+            launch("missing", 0),
+            Some(CodegenError::NotAKernel("missing".to_string()))
+        );
+        assert_eq!(
+            launch("scale", 2),
+            Some(CodegenError::ArgumentCount {
+                function: "scale".to_string(),
+                expected: 1,
+                found: 2
+            })
+        );
+        // shared memory belongs to device code
+        let shared = |c: Context| {
+            c.shared(CType::F32, "s".to_string())?;
+            Ok(c)
+        };
+        assert_eq!(
+            context.shared(CType::F32, "s".to_string()).err(),
+            Some(CodegenError::OutsideDeviceCode("shared memory"))
+        );
+        assert!(
+            context
+                .def_cuda(
+                    FunctionSpace::Host,
+                    "h".to_string(),
+                    CType::Void,
+                    vec![],
+                    shared
+                )
+                .is_err()
+        );
+        assert!(
+            context
+                .def_cuda(
+                    FunctionSpace::Global,
+                    "bad".to_string(),
+                    CType::I32,
+                    vec![],
+                    Ok
+                )
+                .is_err()
+        );
+        assert_eq!(
+            context.render(),
+            "#include <stdint.h>\n\
+             \n\
+             __constant__ float m_MM_k = 2;\n\
+             \n\
+             __global__ void m_MM_scale(float *m_MM_xs);\n\
+             void m_MM_run(float *m_MM_xs);\n\
+             \n\
+             __global__ void m_MM_scale(float *m_MM_xs) {\n\
+             __shared__ float m_MM_tile[256];\n\
+             uint32_t m_MM_i = threadIdx.x;\n\
+             }\n\
+             \n\
+             void m_MM_run(float *m_MM_xs) {\n\
+             m_MM_scale<<<4, 256>>>(m_MM_xs);\n\
+             }\n"
+        );
+    }
+}
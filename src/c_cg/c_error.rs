@@ -0,0 +1,228 @@
+use std::fmt;
+
+use super::{CDialect, c_openmp::ReductionOp, c_type::CType, c_value::StringPrefix};
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum CodegenError {
+    /// the type has no representation in the dialect
+    UnsupportedType { ty: CType, dialect: CDialect },
+    /// a language feature that cannot be emitted in the dialect,
+    /// or is not implemented at all
+    UnsupportedFeature {
+        feature: &'static str,
+        dialect: CDialect,
+    },
+    /// `Context::cond` needs at least one condition and one block per condition
+    MalformedCond { conditions: usize, blocks: usize },
+    /// a `Context::switch` arm without `case` labels
+    EmptySwitchCase,
+    /// `break`, `continue` or `return` without a statement to leave
+    MisplacedJump(&'static str),
+    /// an OpenMP loop directive over a loop whose init, condition or step
+    /// is not in canonical form
+    NonCanonicalLoop(&'static str),
+    /// `collapse(n)` over fewer than `n` perfectly nested loops
+    CollapseDepth { collapse: usize, depth: usize },
+    /// device memory declared outside a `__global__` or `__device__` function
+    OutsideDeviceCode(&'static str),
+    /// a launch of a function that is not a `__global__` function defined so far
+    NotAKernel(String),
+    /// a call with a different number of arguments than the function takes
+    ArgumentCount {
+        function: String,
+        expected: usize,
+        found: usize,
+    },
+    /// `return` with a value in a `void` function or without one otherwise
+    ReturnMismatch(CType),
+    /// a CUDA `goto` that would jump over the declaration of the variable,
+    /// C++ rejects entering its scope past it
+    JumpOverDeclaration(String),
+    /// a label defined twice in the same function
+    DuplicateLabel(String),
+    /// `goto` to a label the function does not define
+    UndefinedLabel(String),
+    /// a directive refers to a variable that is not in scope
+    UnknownVariable(String),
+    /// a data clause needs the extent of an array the type does not give
+    UnsizedData { variable: String, ty: CType },
+    /// a message of `count` elements does not fit the buffer `variable`
+    MessageOverflow { variable: String, count: usize },
+    /// an MPI reduction operator not defined on the elements of `ty`
+    InvalidReduction { op: ReductionOp, ty: CType },
+    /// `variable` was given as both the send and the receive buffer
+    AliasedBuffers(String),
+    /// `CValue::Array` was given a type that is not `CType::Array`
+    NotAnArray(CType),
+    /// `Context::match_tagged` was given a type that is not `CType::TaggedUnion`
+    NotATaggedUnion(CType),
+    /// a match arm names a variant the tagged union does not have
+    UnknownVariant { ty: String, variant: String },
+    /// a match has two arms for the same variant
+    DuplicateArm { ty: String, variant: String },
+    /// a match without default arm does not cover these variants
+    NonExhaustiveMatch { ty: String, missing: Vec<String> },
+    /// the character needs more than one code unit of the literal's encoding
+    InvalidCharLiteral { value: char, prefix: StringPrefix },
+    /// an integer literal outside the range of its type
+    IntegerOutOfRange { value: i128, ty: CType },
+    /// a finite float literal that overflows `ty`, `value` is printed as a
+    /// string to keep the error `Eq`
+    FloatOutOfRange { value: String, ty: CType },
+    /// `offsetof` of a field the struct does not have
+    UnknownField { ty: CType, field: String },
+    /// a struct type or initializer names the same field twice
+    DuplicateField(String),
+    /// a named type is defined twice with different definitions
+    ConflictingType(String),
+    /// an enum or tagged union without variants, C has no empty enums
+    NoVariants(String),
+    /// the underlying type of an enum must be an integer type
+    InvalidEnumType(CType),
+    /// the enumerator does not fit the underlying type of its enum
+    EnumeratorOutOfRange {
+        variant: String,
+        value: i128,
+        ty: CType,
+    },
+    /// bit-field members must have an integer type
+    InvalidBitFieldType { field: String, ty: CType },
+    /// the width exceeds the base type, or is zero on a named member
+    InvalidBitFieldWidth {
+        field: String,
+        width: usize,
+        max: usize,
+    },
+}
+
+pub type CResult<T> = Result<T, CodegenError>;
+
+impl fmt::Display for CodegenError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            CodegenError::UnsupportedType { ty, dialect } => {
+                write!(f, "type {:?} is not supported in dialect {:?}", ty, dialect)
+            }
+            CodegenError::UnsupportedFeature { feature, dialect } => {
+                write!(f, "{} is not supported in dialect {:?}", feature, dialect)
+            }
+            CodegenError::MalformedCond { conditions, blocks } => write!(
+                f,
+                "malformed cond expecting at least one condition and one block, got {} conditions and {} blocks",
+                conditions, blocks
+            ),
+            CodegenError::EmptySwitchCase => write!(f, "switch case without labels"),
+            CodegenError::MisplacedJump(statement) => {
+                write!(
+                    f,
+                    "`{}` has no enclosing statement to jump out of",
+                    statement
+                )
+            }
+            CodegenError::NonCanonicalLoop(part) => {
+                write!(
+                    f,
+                    "the {} of the loop is not in OpenMP canonical form",
+                    part
+                )
+            }
+            CodegenError::CollapseDepth { collapse, depth } => write!(
+                f,
+                "collapse({}) over a nest of {} perfectly nested loops",
+                collapse, depth
+            ),
+            CodegenError::OutsideDeviceCode(feature) => {
+                write!(f, "{} can only be declared in device code", feature)
+            }
+            CodegenError::NotAKernel(name) => {
+                write!(f, "`{}` is not a `__global__` function", name)
+            }
+            CodegenError::ArgumentCount {
+                function,
+                expected,
+                found,
+            } => write!(
+                f,
+                "`{}` takes {} arguments, got {}",
+                function, expected, found
+            ),
+            CodegenError::ReturnMismatch(ty) => {
+                write!(f, "`return` does not match the return type {:?}", ty)
+            }
+            CodegenError::JumpOverDeclaration(name) => {
+                write!(f, "a `goto` would jump over the declaration of `{}`", name)
+            }
+            CodegenError::DuplicateLabel(name) => write!(f, "duplicate label `{}`", name),
+            CodegenError::UndefinedLabel(name) => write!(f, "undefined label `{}`", name),
+            CodegenError::UnknownVariable(name) => write!(f, "unknown variable `{}`", name),
+            CodegenError::UnsizedData { variable, ty } => write!(
+                f,
+                "the extent of `{}` of type {:?} is unknown, data clauses need sized arrays",
+                variable, ty
+            ),
+            CodegenError::MessageOverflow { variable, count } => write!(
+                f,
+                "a message of {} elements does not fit `{}`",
+                count, variable
+            ),
+            CodegenError::InvalidReduction { op, ty } => {
+                write!(f, "{:?} is not defined on the elements of {:?}", op, ty)
+            }
+            CodegenError::AliasedBuffers(variable) => write!(
+                f,
+                "`{}` cannot be both the send and the receive buffer",
+                variable
+            ),
+            CodegenError::NotAnArray(ty) => write!(f, "expected an array type, got {:?}", ty),
+            CodegenError::NotATaggedUnion(ty) => {
+                write!(f, "expected a tagged union type, got {:?}", ty)
+            }
+            CodegenError::UnknownVariant { ty, variant } => {
+                write!(f, "`{}` has no variant `{}`", ty, variant)
+            }
+            CodegenError::DuplicateArm { ty, variant } => {
+                write!(f, "match on `{}` has two arms for `{}`", ty, variant)
+            }
+            CodegenError::NonExhaustiveMatch { ty, missing } => {
+                write!(f, "match on `{}` does not cover {}", ty, missing.join(", "))
+            }
+            CodegenError::InvalidCharLiteral { value, prefix } => write!(
+                f,
+                "{:?} does not fit in a single {:?} character literal",
+                value, prefix
+            ),
+            CodegenError::IntegerOutOfRange { value, ty } => {
+                write!(f, "integer literal {} does not fit in {:?}", value, ty)
+            }
+            CodegenError::FloatOutOfRange { value, ty } => {
+                write!(f, "float literal {} overflows {:?}", value, ty)
+            }
+            CodegenError::UnknownField { ty, field } => {
+                write!(f, "{:?} has no field `{}`", ty, field)
+            }
+            CodegenError::DuplicateField(name) => write!(f, "duplicate field `{}`", name),
+            CodegenError::ConflictingType(name) => {
+                write!(f, "type `{}` is already defined differently", name)
+            }
+            CodegenError::NoVariants(name) => write!(f, "`{}` has no variants", name),
+            CodegenError::InvalidEnumType(ty) => {
+                write!(f, "enum underlying type {:?} is not an integer type", ty)
+            }
+            CodegenError::EnumeratorOutOfRange { variant, value, ty } => write!(
+                f,
+                "enumerator `{}` = {} does not fit in {:?}",
+                variant, value, ty
+            ),
+            CodegenError::InvalidBitFieldType { field, ty } => {
+                write!(f, "bit-field `{}` has non-integer type {:?}", field, ty)
+            }
+            CodegenError::InvalidBitFieldWidth { field, width, max } => write!(
+                f,
+                "bit-field `{}` has width {}, expected 1 to {} bits",
+                field, width, max
+            ),
+        }
+    }
+}
+
+impl std::error::Error for CodegenError {}
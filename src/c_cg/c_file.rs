@@ -1,13 +1,358 @@
+use std::collections::{BTreeMap, BTreeSet, HashMap};
+
+use super::{CDialect, KernelTarget, c_arch::DataModel, c_type::CType};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Visibility {
+    /// external linkage, declared `extern` in the generated header
+    Exported,
+    /// `static`, only visible inside the implementation file
+    Static,
+}
+
+/// a function definition produced by `Context::def`
+pub struct CFunction {
+    /// `ret name(args)` without storage class and the trailing `;`
+    pub prototype: String,
+    pub body: String,
+    pub visibility: Visibility,
+    /// entry points like the GLSL `main` are never prototyped
+    pub entry_point: bool,
+}
+
+impl CFunction {
+    fn declaration(&self) -> String {
+        match self.visibility {
+            Visibility::Exported => format!("{};", self.prototype),
+            Visibility::Static => format!("static {};", self.prototype),
+        }
+    }
+
+    fn definition(&self) -> String {
+        let storage = match self.visibility {
+            Visibility::Exported => "",
+            Visibility::Static => "static ",
+        };
+        format!("{}{} {{\n{}}}\n", storage, self.prototype, self.body)
+    }
+}
+
+#[derive(Default)]
 pub struct CFile {
+    pub dialect: CDialect,
+    /// `#version` line heading shader sources
+    pub version: Option<String>,
+    /// `None` prints integers as `<stdint.h>` types, otherwise as the
+    /// builtin types of the data model
+    pub data_model: Option<DataModel>,
+    /// flags the file must be compiled with, e.g. `-fopenmp`
+    pub compiler_flags: BTreeSet<String>,
+    /// headers in include form, e.g. `<stdint.h>` or `"foo.h"`
+    pub includes: BTreeSet<String>,
+    /// preprocessor checks and pragmas the code relies on, e.g. the
+    /// `_OPENMP` guard, emitted after the includes in a section of their own
+    pub preamble: Vec<String>,
+    /// `struct name;` declarations, emitted before any type definition
+    pub forward_declarations: Vec<String>,
+    /// struct definitions and typedefs in the order they were generated,
+    /// a type is always registered after the types it depends on
+    pub type_definitions: Vec<String>,
+    /// registry of the types defined so far and how they are spelled in C
+    pub types: HashMap<CType, String>,
+    /// C names taken by the types in `types`
+    pub type_names: BTreeSet<String>,
+    /// MPI datatype expressions of the types sent so far, derived datatypes
+    /// are built by a generated function
+    pub mpi_datatypes: HashMap<CType, String>,
+    /// C names of the `__global__` functions and how many arguments they take
+    pub kernels: BTreeMap<String, usize>,
     pub global_inline_c: Vec<String>,
+    pub functions: Vec<CFunction>,
 }
 
-impl CFile {}
+/// append `lines` as a paragraph, separated from previous output by a blank line
+fn push_section<'a>(out: &mut String, lines: impl IntoIterator<Item = &'a str>) {
+    let mut lines = lines.into_iter().peekable();
+    if lines.peek().is_none() {
+        return;
+    }
+    if !out.is_empty() {
+        out.push('\n');
+    }
+    for line in lines {
+        out.push_str(line);
+        if !line.ends_with('\n') {
+            out.push('\n');
+        }
+    }
+}
+
+/// `foo/bar-baz.h` => `FOO_BAR_BAZ_H`
+fn include_guard(header_name: &str) -> String {
+    header_name
+        .chars()
+        .map(|c| {
+            if c.is_ascii_alphanumeric() {
+                c.to_ascii_uppercase()
+            } else {
+                '_'
+            }
+        })
+        .collect()
+}
+
+impl CFile {
+    pub fn forward_declaration(&mut self, code: String) -> &mut Self {
+        if !self.forward_declarations.contains(&code) {
+            self.forward_declarations.push(code);
+        }
+        self
+    }
+
+    /// add `code` to the preamble unless it is already there
+    pub fn preamble(&mut self, code: &str) -> &mut Self {
+        if !self.preamble.iter().any(|p| p == code) {
+            self.preamble.push(code.to_string());
+        }
+        self
+    }
+
+    pub fn include(&mut self, header: &str) -> &mut Self {
+        let header = if header.starts_with('<') || header.starts_with('"') {
+            header.to_string()
+        } else {
+            format!("<{}>", header)
+        };
+        self.includes.insert(header);
+        self
+    }
+
+    /// render the whole translation unit
+    ///
+    /// the order is: includes, preamble, forward declarations, type definitions,
+    /// globals, function prototypes and function definitions, so that
+    /// functions can reference each other regardless of definition order
+    pub fn render(&self) -> String {
+        self.render_with("")
+    }
+
+    /// render with `top_level` appended to the globals, used by `Context`
+    /// to place statements emitted at file scope
+    pub(crate) fn render_with(&self, top_level: &str) -> String {
+        let mut out = String::new();
+        if let Some(version) = &self.version {
+            out.push_str(&format!("#version {}\n", version));
+        }
+        self.render_declarations(&mut out);
+        push_section(
+            &mut out,
+            self.global_inline_c
+                .iter()
+                .map(String::as_str)
+                .chain(Some(top_level).filter(|s| !s.is_empty())),
+        );
+        self.render_functions(&mut out, true);
+        out
+    }
+
+    /// render the header of a header/implementation pair
+    ///
+    /// the header holds includes, the preamble, types and `extern` prototypes
+    /// of the exported functions, wrapped in include guards derived from
+    /// `header_name`
+    pub fn render_header(&self, header_name: &str) -> String {
+        let guard = include_guard(header_name);
+        let mut body = String::new();
+        self.render_types(&mut body);
+        let prototypes = self
+            .functions
+            .iter()
+            .filter(|f| f.visibility == Visibility::Exported)
+            .map(|f| format!("extern {};", f.prototype))
+            .collect::<Vec<_>>();
+        push_section(&mut body, prototypes.iter().map(String::as_str));
+
+        let mut out = format!("#ifndef {}\n#define {}\n", guard, guard);
+        self.render_includes(&mut out);
+        push_section(&mut out, self.preamble.iter().map(String::as_str));
+        push_section(&mut out, ["#ifdef __cplusplus", "extern \"C\" {", "#endif"]);
+        push_section(&mut out, Some(body.as_str()).filter(|s| !s.is_empty()));
+        push_section(&mut out, ["#ifdef __cplusplus", "}", "#endif"]);
+        push_section(&mut out, [format!("#endif /* {} */", guard).as_str()]);
+        out
+    }
+
+    /// render the implementation file matching `render_header(header_name)`
+    pub fn render_source(&self, header_name: &str) -> String {
+        self.render_source_with(header_name, "")
+    }
 
-impl Default for CFile {
-    fn default() -> Self {
-        Self {
-            global_inline_c: Default::default(),
+    pub(crate) fn render_source_with(&self, header_name: &str, top_level: &str) -> String {
+        let mut out = format!("#include \"{}\"\n", header_name);
+        push_section(
+            &mut out,
+            self.global_inline_c
+                .iter()
+                .map(String::as_str)
+                .chain(Some(top_level).filter(|s| !s.is_empty())),
+        );
+        self.render_functions(&mut out, false);
+        out
+    }
+
+    fn render_declarations(&self, out: &mut String) {
+        self.render_includes(out);
+        push_section(out, self.preamble.iter().map(String::as_str));
+        self.render_types(out);
+    }
+
+    fn render_includes(&self, out: &mut String) {
+        let includes = self
+            .includes
+            .iter()
+            .map(|header| format!("#include {}", header))
+            .collect::<Vec<_>>();
+        push_section(out, includes.iter().map(String::as_str));
+    }
+
+    fn render_types(&self, out: &mut String) {
+        // GLSL has no incomplete struct types
+        if self.dialect != CDialect::Kernel(KernelTarget::GLSL) {
+            push_section(out, self.forward_declarations.iter().map(String::as_str));
         }
+        push_section(out, self.type_definitions.iter().map(String::as_str));
+    }
+
+    /// prototypes followed by definitions, exported prototypes are skipped
+    /// when they already come from the header
+    fn render_functions(&self, out: &mut String, with_exported_prototypes: bool) {
+        let prototypes = self
+            .functions
+            .iter()
+            .filter(|f| !f.entry_point)
+            .filter(|f| with_exported_prototypes || f.visibility == Visibility::Static)
+            .map(CFunction::declaration)
+            .collect::<Vec<_>>();
+        push_section(out, prototypes.iter().map(String::as_str));
+        for function in &self.functions {
+            push_section(out, [function.definition().as_str()]);
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn file() -> CFile {
+        let mut file = CFile::default();
+        file.include("stdio.h");
+        file.forward_declarations.push("struct a;".to_string());
+        file.type_definitions
+            .push("struct a {\nint x;\n};".to_string());
+        file.global_inline_c.push("int g = 1;".to_string());
+        file.functions.push(CFunction {
+            prototype: "int f(void)".to_string(),
+            body: "return g;\n".to_string(),
+            visibility: Visibility::Exported,
+            entry_point: false,
+        });
+        file.functions.push(CFunction {
+            prototype: "int h(void)".to_string(),
+            body: "return f();\n".to_string(),
+            visibility: Visibility::Static,
+            entry_point: false,
+        });
+        file
+    }
+
+    #[test]
+    fn test_render_order() {
+        assert_eq!(
+            file().render(),
+            "#include <stdio.h>\n\
+             \n\
+             struct a;\n\
+             \n\
+             struct a {\nint x;\n};\n\
+             \n\
+             int g = 1;\n\
+             \n\
+             int f(void);\n\
+             static int h(void);\n\
+             \n\
+             int f(void) {\nreturn g;\n}\n\
+             \n\
+             static int h(void) {\nreturn f();\n}\n"
+        );
+    }
+
+    #[test]
+    fn test_render_header_and_source() {
+        let file = file();
+        assert_eq!(
+            file.render_header("gen/a-b.h"),
+            "#ifndef GEN_A_B_H\n\
+             #define GEN_A_B_H\n\
+             \n\
+             #include <stdio.h>\n\
+             \n\
+             #ifdef __cplusplus\n\
+             extern \"C\" {\n\
+             #endif\n\
+             \n\
+             struct a;\n\
+             \n\
+             struct a {\nint x;\n};\n\
+             \n\
+             extern int f(void);\n\
+             \n\
+             #ifdef __cplusplus\n\
+             }\n\
+             #endif\n\
+             \n\
+             #endif /* GEN_A_B_H */\n"
+        );
+        assert_eq!(
+            file.render_source("gen/a-b.h"),
+            "#include \"gen/a-b.h\"\n\
+             \n\
+             int g = 1;\n\
+             \n\
+             static int h(void);\n\
+             \n\
+             int f(void) {\nreturn g;\n}\n\
+             \n\
+             static int h(void) {\nreturn f();\n}\n"
+        );
+    }
+
+    #[test]
+    fn test_render_preamble() {
+        let mut file = file();
+        let guard = "#if !defined(_OPENMP)\n#error \"compile with -fopenmp\"\n#endif";
+        file.preamble(guard).preamble(guard);
+        assert!(file.render().starts_with(
+            "#include <stdio.h>\n\
+             \n\
+             #if !defined(_OPENMP)\n\
+             #error \"compile with -fopenmp\"\n\
+             #endif\n\
+             \n\
+             struct a;\n"
+        ));
+    }
+
+    #[test]
+    fn test_render_split_preamble() {
+        let mut file = file();
+        file.preamble("#pragma OPENCL EXTENSION cl_khr_fp64 : enable");
+        let header = file.render_header("a.h");
+        let pragma = header.find("cl_khr_fp64").unwrap();
+        assert!(header.find("#include <stdio.h>").unwrap() < pragma);
+        assert!(pragma < header.find("extern \"C\"").unwrap());
+        assert!(pragma < header.find("struct a;").unwrap());
+        assert_eq!(header.matches("cl_khr_fp64").count(), 1);
+        assert!(!file.render_source("a.h").contains("cl_khr_fp64"));
     }
 }
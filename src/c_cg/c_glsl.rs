@@ -0,0 +1,562 @@
+use std::sync::{Arc, Mutex};
+
+use super::{
+    CDialect, KernelTarget,
+    c_error::{CResult, CodegenError},
+    c_file::{CFile, CFunction, Visibility},
+    c_stmt::{Context, Variable},
+    c_type::{CType, GLSLType, ImageFormat, TextureDim, check_unique_fields, members_to_c},
+};
+
+const GLSL: CDialect = CDialect::Kernel(KernelTarget::GLSL);
+
+/// `layout(set = S, binding = B)` of a resource, the set is Vulkan only
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Binding {
+    pub set: Option<usize>,
+    pub binding: usize,
+}
+
+impl Binding {
+    fn qualifiers(&self) -> Vec<String> {
+        self.set
+            .map(|set| format!("set = {}", set))
+            .into_iter()
+            .chain(Some(format!("binding = {}", self.binding)))
+            .collect()
+    }
+}
+
+fn layout(qualifiers: Vec<String>) -> String {
+    format!("layout({}) ", qualifiers.join(", "))
+}
+
+impl TextureDim {
+    fn to_glsl(self) -> &'static str {
+        match self {
+            TextureDim::D1 => "1D",
+            TextureDim::D2 => "2D",
+            TextureDim::D3 => "3D",
+            TextureDim::Cube => "Cube",
+            TextureDim::D2Array => "2DArray",
+            TextureDim::Buffer => "Buffer",
+        }
+    }
+}
+
+impl ImageFormat {
+    fn to_glsl(self) -> &'static str {
+        match self {
+            ImageFormat::Rgba32f => "rgba32f",
+            ImageFormat::Rgba16f => "rgba16f",
+            ImageFormat::Rg32f => "rg32f",
+            ImageFormat::R32f => "r32f",
+            ImageFormat::Rgba8 => "rgba8",
+            ImageFormat::Rgba32i => "rgba32i",
+            ImageFormat::R32i => "r32i",
+            ImageFormat::Rgba32ui => "rgba32ui",
+            ImageFormat::R32ui => "r32ui",
+        }
+    }
+
+    /// the sampled type of images with this format
+    fn element(self) -> CType {
+        match self {
+            ImageFormat::Rgba32i | ImageFormat::R32i => CType::I32,
+            ImageFormat::Rgba32ui | ImageFormat::R32ui => CType::U32,
+            _ => CType::F32,
+        }
+    }
+}
+
+/// opaque types can only be declared as uniforms
+fn is_opaque(ty: &CType) -> bool {
+    matches!(
+        ty,
+        CType::GLSLExtension(
+            GLSLType::Sampler { .. } | GLSLType::Image { .. } | GLSLType::AtomicUint
+        )
+    )
+}
+
+/// GLSL spelling of `ty`, `None` for the types printed the same way as in C
+/// (structs, arrays and const), any type GLSL does not have is an error
+pub(crate) fn glsl_type(ty: &CType, dialect: CDialect) -> CResult<Option<String>> {
+    let unsupported = || CodegenError::UnsupportedType {
+        ty: ty.clone(),
+        dialect,
+    };
+    let scalar = match ty {
+        CType::Void => "void",
+        CType::Bool => "bool",
+        CType::I32 => "int",
+        CType::U32 => "uint",
+        CType::F32 => "float",
+        CType::F64 => "double",
+        CType::Struct { repr: None, .. } | CType::Array { .. } | CType::Const { .. } => {
+            return Ok(None);
+        }
+        CType::GLSLExtension(GLSLType::Vec { ty: element, size }) => {
+            let prefix = match element.as_ref() {
+                CType::F32 => "",
+                CType::F64 => "d",
+                CType::I32 => "i",
+                CType::U32 => "u",
+                CType::Bool => "b",
+                _ => return Err(unsupported()),
+            };
+            if !(2..=4).contains(size) {
+                return Err(unsupported());
+            }
+            return Ok(Some(format!("{}vec{}", prefix, size)));
+        }
+        CType::GLSLExtension(GLSLType::Mat {
+            ty: element,
+            rows,
+            cols,
+        }) => {
+            let prefix = match element.as_ref() {
+                CType::F32 => "",
+                CType::F64 => "d",
+                _ => return Err(unsupported()),
+            };
+            if !(2..=4).contains(rows) || !(2..=4).contains(cols) {
+                return Err(unsupported());
+            }
+            // `matCxR` has C columns and R rows
+            return Ok(Some(if rows == cols {
+                format!("{}mat{}", prefix, cols)
+            } else {
+                format!("{}mat{}x{}", prefix, cols, rows)
+            }));
+        }
+        CType::GLSLExtension(GLSLType::Sampler { ty: element, dim }) => {
+            let prefix = opaque_prefix(element).ok_or_else(unsupported)?;
+            return Ok(Some(format!("{}sampler{}", prefix, dim.to_glsl())));
+        }
+        CType::GLSLExtension(GLSLType::Image {
+            ty: element,
+            dim,
+            format,
+        }) => {
+            let prefix = opaque_prefix(element).ok_or_else(unsupported)?;
+            if **element != format.element() {
+                return Err(unsupported());
+            }
+            return Ok(Some(format!("{}image{}", prefix, dim.to_glsl())));
+        }
+        CType::GLSLExtension(GLSLType::AtomicUint) => "atomic_uint",
+        _ => return Err(unsupported()),
+    };
+    Ok(Some(scalar.to_string()))
+}
+
+/// `i`/`u` prefix of integer samplers and images
+fn opaque_prefix(element: &CType) -> Option<&'static str> {
+    match element {
+        CType::F32 => Some(""),
+        CType::I32 => Some("i"),
+        CType::U32 => Some("u"),
+        _ => None,
+    }
+}
+
+impl Context {
+    /// a context generating a GLSL shader, `version` is the `#version` line
+    /// e.g. `450` or `310 es`
+    pub fn glsl(module_name: String, version: &str) -> Self {
+        let c_file = CFile {
+            dialect: GLSL,
+            version: Some(version.to_string()),
+            ..Default::default()
+        };
+        Self {
+            c_file: Arc::new(Mutex::new(c_file)),
+            module: module_name,
+            dialect: GLSL,
+            variables: Default::default(),
+            current_source: Default::default(),
+            last_statement: Default::default(),
+            scopes: Default::default(),
+            jumps: Default::default(),
+            device: false,
+        }
+    }
+
+    fn check_glsl(&self, feature: &'static str) -> CResult<()> {
+        if self.dialect != GLSL {
+            return Err(CodegenError::UnsupportedFeature {
+                feature,
+                dialect: self.dialect,
+            });
+        }
+        Ok(())
+    }
+
+    /// declare a global shader interface variable, visible to every function
+    fn glsl_global(&self, qualifiers: &str, ty: CType, name: Variable) -> CResult<&Self> {
+        let c_name = self.c_name(&name);
+        let declaration = ty.declare(&c_name, self.dialect, self)?;
+        self.variables.lock().unwrap().insert(name, ty);
+        self.global_inline_c(format!("{}{};", qualifiers, declaration));
+        Ok(self)
+    }
+
+    fn check_not_opaque(&self, ty: &CType) -> CResult<()> {
+        if is_opaque(ty) {
+            return Err(CodegenError::UnsupportedType {
+                ty: ty.clone(),
+                dialect: self.dialect,
+            });
+        }
+        Ok(())
+    }
+
+    /// `layout(location = N) in ty name;`
+    pub fn glsl_input(&self, location: usize, ty: CType, name: Variable) -> CResult<&Self> {
+        self.check_glsl("shader inputs")?;
+        self.check_not_opaque(&ty)?;
+        self.glsl_global(&format!("layout(location = {}) in ", location), ty, name)
+    }
+
+    /// `layout(location = N) out ty name;`
+    pub fn glsl_output(&self, location: usize, ty: CType, name: Variable) -> CResult<&Self> {
+        self.check_glsl("shader outputs")?;
+        self.check_not_opaque(&ty)?;
+        self.glsl_global(&format!("layout(location = {}) out ", location), ty, name)
+    }
+
+    /// `uniform ty name;`, with an explicit location if given
+    pub fn glsl_uniform(
+        &self,
+        location: Option<usize>,
+        ty: CType,
+        name: Variable,
+    ) -> CResult<&Self> {
+        self.check_glsl("uniforms")?;
+        self.check_not_opaque(&ty)?;
+        let layout = location
+            .map(|location| format!("layout(location = {}) ", location))
+            .unwrap_or_default();
+        self.glsl_global(&format!("{}uniform ", layout), ty, name)
+    }
+
+    /// a sampler, image or atomic counter uniform bound to `binding`,
+    /// images also get their format qualifier
+    pub fn glsl_resource(&self, binding: Binding, ty: CType, name: Variable) -> CResult<&Self> {
+        self.check_glsl("shader resources")?;
+        let mut qualifiers = binding.qualifiers();
+        match &ty {
+            CType::GLSLExtension(GLSLType::Sampler { .. }) => {}
+            CType::GLSLExtension(GLSLType::Image { format, .. }) => {
+                qualifiers.push(format.to_glsl().to_string());
+            }
+            // Vulkan has no atomic counters
+            CType::GLSLExtension(GLSLType::AtomicUint) if binding.set.is_some() => {
+                return Err(CodegenError::UnsupportedFeature {
+                    feature: "atomic counters in descriptor sets",
+                    dialect: self.dialect,
+                });
+            }
+            CType::GLSLExtension(GLSLType::AtomicUint) => {}
+            _ => {
+                return Err(CodegenError::UnsupportedType {
+                    ty,
+                    dialect: self.dialect,
+                });
+            }
+        }
+        self.glsl_global(&format!("{}uniform ", layout(qualifiers)), ty, name)
+    }
+
+    /// a `std430` shader storage block, the last field may be an unsized
+    /// array; fields are accessed as members of `instance`
+    pub fn glsl_buffer(
+        &self,
+        binding: Binding,
+        block: &str,
+        fields: Vec<(String, CType)>,
+        instance: Variable,
+    ) -> CResult<&Self> {
+        self.check_glsl("shader storage blocks")?;
+        check_unique_fields(fields.iter().map(|(name, _)| name))?;
+        if let Some((_, ty)) = fields.iter().find(|(_, ty)| is_opaque(ty)) {
+            return Err(CodegenError::UnsupportedType {
+                ty: ty.clone(),
+                dialect: self.dialect,
+            });
+        }
+        let mut qualifiers = vec!["std430".to_string()];
+        qualifiers.extend(binding.qualifiers());
+        let declaration = format!(
+            "{}buffer {} {{\n{}\n}} {};",
+            layout(qualifiers),
+            self.c_name(block),
+            members_to_c(&fields, self.dialect, self)?,
+            self.c_name(&instance)
+        );
+        let ty = CType::GLSLExtension(GLSLType::Block {
+            name: block.to_string(),
+            fields,
+        });
+        self.variables.lock().unwrap().insert(instance, ty);
+        self.global_inline_c(declaration);
+        Ok(self)
+    }
+
+    /// the `void main()` entry point of the shader
+    pub fn entry_point(&self, body: impl Fn(Self) -> CResult<Self>) -> CResult<&Self> {
+        self.check_glsl("shader entry points")?;
+        let (prototype, body) = self.function("main", CType::Void, vec![], body)?;
+        self.c_file.lock().unwrap().functions.push(CFunction {
+            prototype,
+            body,
+            visibility: Visibility::Exported,
+            entry_point: true,
+        });
+        Ok(self)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::c_cg::{ToC, c_value::CValue};
+
+    fn vec(ty: CType, size: usize) -> CType {
+        CType::GLSLExtension(GLSLType::Vec {
+            ty: Box::new(ty),
+            size,
+        })
+    }
+
+    #[test]
+    fn test_glsl_types() {
+        let context = Context::glsl("m".to_string(), "450");
+        let to_c = |ty: CType| ty.to_c(GLSL, &context);
+        assert_eq!(to_c(vec(CType::F32, 3)).unwrap(), "vec3");
+        assert_eq!(to_c(vec(CType::I32, 4)).unwrap(), "ivec4");
+        assert_eq!(to_c(vec(CType::Bool, 2)).unwrap(), "bvec2");
+        assert_eq!(
+            to_c(CType::GLSLExtension(GLSLType::Mat {
+                ty: Box::new(CType::F64),
+                rows: 2,
+                cols: 3,
+            }))
+            .unwrap(),
+            "dmat3x2"
+        );
+        assert_eq!(
+            to_c(CType::GLSLExtension(GLSLType::Mat {
+                ty: Box::new(CType::F32),
+                rows: 4,
+                cols: 4,
+            }))
+            .unwrap(),
+            "mat4"
+        );
+        assert_eq!(
+            to_c(CType::GLSLExtension(GLSLType::Sampler {
+                ty: Box::new(CType::I32),
+                dim: TextureDim::D3,
+            }))
+            .unwrap(),
+            "isampler3D"
+        );
+        assert_eq!(
+            to_c(CType::GLSLExtension(GLSLType::Sampler {
+                ty: Box::new(CType::F32),
+                dim: TextureDim::Cube,
+            }))
+            .unwrap(),
+            "samplerCube"
+        );
+        // the format does not match the sampled type
+        assert!(
+            to_c(CType::GLSLExtension(GLSLType::Image {
+                ty: Box::new(CType::U32),
+                dim: TextureDim::D2,
+                format: ImageFormat::Rgba32f,
+            }))
+            .is_err()
+        );
+        assert!(to_c(vec(CType::F32, 5)).is_err());
+        assert!(to_c(CType::I64).is_err());
+        assert!(
+            to_c(CType::Pointer {
+                ty: Box::new(CType::F32)
+            })
+            .is_err()
+        );
+    }
+
+    #[test]
+    fn test_glsl_shader() {
+        let context = Context::glsl("m".to_string(), "450");
+        context
+            .glsl_input(0, vec(CType::F32, 3), "color".to_string())
+            .unwrap()
+            .glsl_output(0, vec(CType::F32, 4), "frag".to_string())
+            .unwrap()
+            .glsl_uniform(None, CType::F32, "alpha".to_string())
+            .unwrap()
+            .entry_point(|c| {
+                c.set(
+                    vec(CType::F32, 4),
+                    "frag".to_string(),
+                    CValue::FunctionCall(
+                        Box::new(CValue::Extern("vec4".to_string())),
+                        vec![
+                            CValue::Variable("color".to_string()),
+                            CValue::Variable("alpha".to_string()),
+                        ],
+                    ),
+                )?;
+                Ok(c)
+            })
+            .unwrap();
+        assert_eq!(
+            context.render(),
+            "#version 450\n\
+             \n\
+             layout(location = 0) in vec3 m_MM_color;\n\
+             layout(location = 0) out vec4 m_MM_frag;\n\
+             uniform float m_MM_alpha;\n\
+             \n\
+             void main() {\n\
+             m_MM_frag = vec4(m_MM_color, m_MM_alpha);\n\
+             }\n"
+        );
+    }
+
+    #[test]
+    fn test_glsl_struct() {
+        let context = Context::glsl("m".to_string(), "310 es");
+        let light = CType::Struct {
+            name: Some("light".to_string()),
+            repr: None,
+            fields: vec![
+                ("dir".to_string(), vec(CType::F32, 3)),
+                ("power".to_string(), CType::F32),
+            ],
+        };
+        context
+            .glsl_uniform(None, light.clone(), "sun".to_string())
+            .unwrap()
+            .entry_point(|c| {
+                c.block(&light, |c, _| Ok(c))?;
+                Ok(c)
+            })
+            .unwrap();
+        let source = context.render();
+        assert!(source.starts_with(
+            "#version 310 es\n\
+             \n\
+             struct m_MM_light {\n\
+             vec3 dir;\n\
+             float power;\n\
+             };\n\
+             \n\
+             uniform m_MM_light m_MM_sun;\n\
+             \n\
+             void main() {\n\
+             m_MM_light m_MM_Z5FztmpZ5Fz"
+        ));
+        // no forward declarations, and no identifier reserved by GLSL ES
+        assert!(!source.contains("struct m_MM_light;"));
+        assert!(!source.contains("__"));
+    }
+
+    #[test]
+    fn test_glsl_resources() {
+        let context = Context::glsl("m".to_string(), "450");
+        let binding = |set, binding| Binding { set, binding };
+        context
+            .glsl_resource(
+                binding(Some(0), 0),
+                CType::GLSLExtension(GLSLType::Sampler {
+                    ty: Box::new(CType::F32),
+                    dim: TextureDim::D2,
+                }),
+                "tex".to_string(),
+            )
+            .unwrap()
+            .glsl_resource(
+                binding(Some(0), 1),
+                CType::GLSLExtension(GLSLType::Image {
+                    ty: Box::new(CType::F32),
+                    dim: TextureDim::D2,
+                    format: ImageFormat::Rgba32f,
+                }),
+                "img".to_string(),
+            )
+            .unwrap()
+            .glsl_buffer(
+                binding(Some(1), 0),
+                "Particles",
+                vec![
+                    ("count".to_string(), CType::U32),
+                    (
+                        "data".to_string(),
+                        CType::Array {
+                            ty: Box::new(vec(CType::F32, 4)),
+                            size: None,
+                        },
+                    ),
+                ],
+                "particles".to_string(),
+            )
+            .unwrap()
+            .glsl_resource(
+                binding(None, 2),
+                CType::GLSLExtension(GLSLType::AtomicUint),
+                "counter".to_string(),
+            )
+            .unwrap();
+        assert!(
+            context
+                .glsl_resource(
+                    binding(Some(0), 3),
+                    CType::GLSLExtension(GLSLType::AtomicUint),
+                    "vk_counter".to_string(),
+                )
+                .is_err()
+        );
+        assert!(
+            context
+                .glsl_resource(binding(None, 3), CType::F32, "x".to_string())
+                .is_err()
+        );
+        // the instance is typed by its block, which cannot declare a value
+        let particles = context.variables.lock().unwrap()["particles"].clone();
+        assert!(matches!(
+            &particles,
+            CType::GLSLExtension(GLSLType::Block { name, .. }) if name == "Particles"
+        ));
+        assert_eq!(
+            context
+                .set(
+                    particles.clone(),
+                    "copy".to_string(),
+                    CValue::Variable("particles".to_string())
+                )
+                .err(),
+            Some(CodegenError::UnsupportedType {
+                ty: particles,
+                dialect: GLSL
+            })
+        );
+        assert_eq!(
+            context.render(),
+            "#version 450\n\
+             \n\
+             layout(set = 0, binding = 0) uniform sampler2D m_MM_tex;\n\
+             layout(set = 0, binding = 1, rgba32f) uniform image2D m_MM_img;\n\
+             layout(std430, set = 1, binding = 0) buffer m_MM_Particles {\n\
+             uint count;\n\
+             vec4 data[];\n\
+             } m_MM_particles;\n\
+             layout(binding = 2) uniform atomic_uint m_MM_counter;\n"
+        );
+    }
+}
@@ -0,0 +1,666 @@
+use super::{
+    CDialect, ToC,
+    c_error::{CResult, CodegenError},
+    c_file::{CFunction, Visibility},
+    c_openmp::ReductionOp,
+    c_stmt::{Context, Variable},
+    c_type::CType,
+    c_value::CValue,
+};
+
+impl ReductionOp {
+    fn mpi_op(self) -> &'static str {
+        match self {
+            ReductionOp::Add => "MPI_SUM",
+            ReductionOp::Mul => "MPI_PROD",
+            ReductionOp::BitAnd => "MPI_BAND",
+            ReductionOp::BitOr => "MPI_BOR",
+            ReductionOp::BitXor => "MPI_BXOR",
+            ReductionOp::And => "MPI_LAND",
+            ReductionOp::Or => "MPI_LOR",
+            ReductionOp::Min => "MPI_MIN",
+            ReductionOp::Max => "MPI_MAX",
+        }
+    }
+}
+
+/// predefined operators apply to predefined datatypes only: bitwise ones
+/// to integers, logical ones to integers and `bool`, the others to numbers
+fn check_reduction(op: ReductionOp, ty: &CType) -> CResult<()> {
+    let element = match ty {
+        CType::Array { ty, .. } | CType::Const { ty } | CType::Typedef { ty, .. } => {
+            return check_reduction(op, ty);
+        }
+        element => element,
+    };
+    let integer = matches!(
+        element,
+        CType::I8
+            | CType::I16
+            | CType::I32
+            | CType::I64
+            | CType::U8
+            | CType::U16
+            | CType::U32
+            | CType::U64
+    );
+    let float = matches!(element, CType::F32 | CType::F64);
+    let valid = match op {
+        ReductionOp::Add | ReductionOp::Mul | ReductionOp::Min | ReductionOp::Max => {
+            integer || float
+        }
+        ReductionOp::BitAnd | ReductionOp::BitOr | ReductionOp::BitXor => integer,
+        ReductionOp::And | ReductionOp::Or => integer || *element == CType::Bool,
+    };
+    if !valid {
+        return Err(CodegenError::InvalidReduction { op, ty: ty.clone() });
+    }
+    Ok(())
+}
+
+/// a message buffer: the buffer address, element count and datatype
+struct Buffer {
+    address: String,
+    count: usize,
+    datatype: String,
+}
+
+impl Context {
+    /// check the dialect and make the file require MPI, build with `mpicc`
+    fn require_mpi(&self) -> CResult<()> {
+        if self.dialect != CDialect::Parallel {
+            return Err(CodegenError::UnsupportedFeature {
+                feature: "MPI",
+                dialect: self.dialect,
+            });
+        }
+        self.include("mpi.h");
+        Ok(())
+    }
+
+    /// the MPI datatype of `ty` and how many of them make up one `ty`,
+    /// structs get a derived datatype built on first use
+    fn mpi_datatype(&self, ty: &CType) -> CResult<(String, usize)> {
+        let predefined = match ty {
+            CType::Bool => "MPI_C_BOOL",
+            CType::I8 => "MPI_INT8_T",
+            CType::I16 => "MPI_INT16_T",
+            CType::I32 => "MPI_INT32_T",
+            CType::I64 => "MPI_INT64_T",
+            CType::U8 => "MPI_UINT8_T",
+            CType::U16 => "MPI_UINT16_T",
+            CType::U32 => "MPI_UINT32_T",
+            CType::U64 => "MPI_UINT64_T",
+            CType::F32 => "MPI_FLOAT",
+            CType::F64 => "MPI_DOUBLE",
+            CType::Array {
+                ty: element,
+                size: Some(size),
+            } => {
+                let (datatype, count) = self.mpi_datatype(element)?;
+                return Ok((datatype, count * size));
+            }
+            CType::Const { ty } | CType::Typedef { ty, .. } => return self.mpi_datatype(ty),
+            CType::Struct { fields, .. } => return Ok((self.mpi_struct(ty, fields)?, 1)),
+            _ => {
+                return Err(CodegenError::UnsupportedType {
+                    ty: ty.clone(),
+                    dialect: self.dialect,
+                });
+            }
+        };
+        Ok((predefined.to_string(), 1))
+    }
+
+    /// a function building and committing the derived datatype of the
+    /// struct `ty` once, resized so arrays of `ty` keep their padding
+    fn mpi_struct(&self, ty: &CType, fields: &[(String, CType)]) -> CResult<String> {
+        if let Some(datatype) = self.c_file.lock().unwrap().mpi_datatypes.get(ty) {
+            return Ok(datatype.clone());
+        }
+        let spelling = ty.to_c(self.dialect, self)?;
+        let (mut lengths, mut offsets, mut types) = (vec![], vec![], vec![]);
+        for (name, field) in fields {
+            let (datatype, count) = self.mpi_datatype(field)?;
+            lengths.push(count.to_string());
+            offsets.push(format!("offsetof({}, {})", spelling, name));
+            types.push(datatype);
+        }
+        self.include("stddef.h");
+        let name = self.temp_name();
+        let body = format!(
+            "static MPI_Datatype datatype = MPI_DATATYPE_NULL;\n\
+             if (datatype == MPI_DATATYPE_NULL) {{\n\
+             int lengths[{n}] = {{ {} }};\n\
+             MPI_Aint offsets[{n}] = {{ {} }};\n\
+             MPI_Datatype types[{n}] = {{ {} }};\n\
+             MPI_Datatype packed;\n\
+             MPI_Type_create_struct({n}, lengths, offsets, types, &packed);\n\
+             MPI_Type_create_resized(packed, 0, sizeof({}), &datatype);\n\
+             MPI_Type_free(&packed);\n\
+             MPI_Type_commit(&datatype);\n\
+             }}\n\
+             return datatype;\n",
+            lengths.join(", "),
+            offsets.join(", "),
+            types.join(", "),
+            spelling,
+            n = fields.len(),
+        );
+        let datatype = format!("{}()", name);
+        let mut c_file = self.c_file.lock().unwrap();
+        c_file.functions.push(CFunction {
+            prototype: format!("MPI_Datatype {}(void)", name),
+            body,
+            visibility: Visibility::Static,
+            entry_point: false,
+        });
+        c_file.mpi_datatypes.insert(ty.clone(), datatype.clone());
+        Ok(datatype)
+    }
+
+    /// the whole of the variable `name` as a message buffer
+    fn mpi_buffer(&self, name: &Variable) -> CResult<Buffer> {
+        let c_name = self.known_variable(name)?;
+        let ty = self.variables.lock().unwrap()[name].clone();
+        if matches!(ty, CType::Pointer { .. } | CType::Array { size: None, .. }) {
+            return Err(CodegenError::UnsizedData {
+                variable: name.clone(),
+                ty,
+            });
+        }
+        let (datatype, count) = self.mpi_datatype(&ty)?;
+        let address = if matches!(ty, CType::Array { .. }) {
+            c_name
+        } else {
+            format!("&{}", c_name)
+        };
+        Ok(Buffer {
+            address,
+            count,
+            datatype,
+        })
+    }
+
+    fn mpi_call(&self, function: &str, args: Vec<String>) -> CResult<&Self> {
+        self.require_mpi()?;
+        self.current_source.lock().unwrap().push_str(&format!(
+            "{}({});\n",
+            function,
+            args.join(", ")
+        ));
+        Ok(self)
+    }
+
+    fn mpi_values(&self, values: Vec<CValue>) -> CResult<Vec<String>> {
+        values
+            .iter()
+            .map(|value| value.to_c(self.dialect, self))
+            .collect()
+    }
+
+    /// `MPI_Init`, passing the `argc`/`argv` variables of `main` if given
+    pub fn mpi_init(&self, args: Option<(Variable, Variable)>) -> CResult<&Self> {
+        let args = match args {
+            Some((argc, argv)) => vec![
+                format!("&{}", self.known_variable(&argc)?),
+                format!("&{}", self.known_variable(&argv)?),
+            ],
+            None => vec!["NULL".to_string(), "NULL".to_string()],
+        };
+        self.include("stddef.h");
+        self.mpi_call("MPI_Init", args)
+    }
+
+    pub fn mpi_finalize(&self) -> CResult<&Self> {
+        self.mpi_call("MPI_Finalize", vec![])
+    }
+
+    /// declare `rank` as the rank of this process in `MPI_COMM_WORLD`
+    pub fn mpi_comm_rank(&self, rank: Variable) -> CResult<&Self> {
+        self.mpi_comm_query("MPI_Comm_rank", rank)
+    }
+
+    /// declare `size` as the number of processes in `MPI_COMM_WORLD`
+    pub fn mpi_comm_size(&self, size: Variable) -> CResult<&Self> {
+        self.mpi_comm_query("MPI_Comm_size", size)
+    }
+
+    /// an existing `name` has to be an `I32` as well
+    fn mpi_comm_query(&self, function: &str, name: Variable) -> CResult<&Self> {
+        self.require_mpi()?;
+        let known = self.variables.lock().unwrap().get(&name).cloned();
+        match known {
+            Some(CType::I32) => {}
+            Some(ty) => {
+                return Err(CodegenError::UnsupportedType {
+                    ty,
+                    dialect: self.dialect,
+                });
+            }
+            None => {
+                let declaration = CType::I32.declare(&self.c_name(&name), self.dialect, self)?;
+                self.variables
+                    .lock()
+                    .unwrap()
+                    .insert(name.clone(), CType::I32);
+                self.current_source
+                    .lock()
+                    .unwrap()
+                    .push_str(&format!("{};\n", declaration));
+            }
+        }
+        let c_name = self.known_variable(&name)?;
+        self.mpi_call(
+            function,
+            vec!["MPI_COMM_WORLD".to_string(), format!("&{}", c_name)],
+        )
+    }
+
+    /// send all of `buffer` to rank `dest`
+    pub fn mpi_send(&self, buffer: Variable, dest: CValue, tag: CValue) -> CResult<&Self> {
+        let buffer = self.mpi_buffer(&buffer)?;
+        let [dest, tag] = self.mpi_values(vec![dest, tag])?.try_into().unwrap();
+        self.mpi_call(
+            "MPI_Send",
+            vec![
+                buffer.address,
+                buffer.count.to_string(),
+                buffer.datatype,
+                dest,
+                tag,
+                "MPI_COMM_WORLD".to_string(),
+            ],
+        )
+    }
+
+    /// receive into all of `buffer` from rank `source`, which may be
+    /// `MPI_ANY_SOURCE`
+    pub fn mpi_recv(&self, buffer: Variable, source: CValue, tag: CValue) -> CResult<&Self> {
+        let buffer = self.mpi_buffer(&buffer)?;
+        let [source, tag] = self.mpi_values(vec![source, tag])?.try_into().unwrap();
+        self.mpi_call(
+            "MPI_Recv",
+            vec![
+                buffer.address,
+                buffer.count.to_string(),
+                buffer.datatype,
+                source,
+                tag,
+                "MPI_COMM_WORLD".to_string(),
+                "MPI_STATUS_IGNORE".to_string(),
+            ],
+        )
+    }
+
+    /// broadcast `buffer` from `root` to every rank
+    pub fn mpi_bcast(&self, buffer: Variable, root: CValue) -> CResult<&Self> {
+        let buffer = self.mpi_buffer(&buffer)?;
+        let root = root.to_c(self.dialect, self)?;
+        self.mpi_call(
+            "MPI_Bcast",
+            vec![
+                buffer.address,
+                buffer.count.to_string(),
+                buffer.datatype,
+                root,
+                "MPI_COMM_WORLD".to_string(),
+            ],
+        )
+    }
+
+    /// `send` and `recv` of the same type for the reductions
+    fn mpi_reduction_buffers(
+        &self,
+        send: &Variable,
+        recv: &Variable,
+        op: ReductionOp,
+    ) -> CResult<[Buffer; 2]> {
+        let send_buffer = self.mpi_buffer(send)?;
+        let ty = self.variables.lock().unwrap()[send].clone();
+        check_reduction(op, &ty)?;
+        let recv_buffer = self.mpi_buffer(recv)?;
+        if send_buffer.datatype != recv_buffer.datatype || send_buffer.count != recv_buffer.count {
+            let ty = self.variables.lock().unwrap()[recv].clone();
+            return Err(CodegenError::UnsupportedType {
+                ty,
+                dialect: self.dialect,
+            });
+        }
+        Ok([send_buffer, recv_buffer])
+    }
+
+    /// combine `send` of every rank element-wise into `recv` on `root`;
+    /// the buffers have to differ, since only the root may reduce in place
+    pub fn mpi_reduce(
+        &self,
+        send: Variable,
+        recv: Variable,
+        op: ReductionOp,
+        root: CValue,
+    ) -> CResult<&Self> {
+        if send == recv {
+            return Err(CodegenError::AliasedBuffers(send));
+        }
+        let [send, recv] = self.mpi_reduction_buffers(&send, &recv, op)?;
+        let root = root.to_c(self.dialect, self)?;
+        self.mpi_call(
+            "MPI_Reduce",
+            vec![
+                send.address,
+                recv.address,
+                send.count.to_string(),
+                send.datatype,
+                op.mpi_op().to_string(),
+                root,
+                "MPI_COMM_WORLD".to_string(),
+            ],
+        )
+    }
+
+    /// like `mpi_reduce`, with the result on every rank; the same variable
+    /// for `send` and `recv` reduces in place
+    pub fn mpi_allreduce(&self, send: Variable, recv: Variable, op: ReductionOp) -> CResult<&Self> {
+        let in_place = send == recv;
+        let [send, recv] = self.mpi_reduction_buffers(&send, &recv, op)?;
+        let send_address = match in_place {
+            true => "MPI_IN_PLACE".to_string(),
+            false => send.address,
+        };
+        self.mpi_call(
+            "MPI_Allreduce",
+            vec![
+                send_address,
+                recv.address,
+                send.count.to_string(),
+                send.datatype,
+                op.mpi_op().to_string(),
+                "MPI_COMM_WORLD".to_string(),
+            ],
+        )
+    }
+
+    /// split `send` on `root` into parts of `count` elements, the part of
+    /// each rank is received into `recv`; `send` has to hold `count`
+    /// elements for every rank, which is only known at run time
+    pub fn mpi_scatter(
+        &self,
+        send: Variable,
+        recv: Variable,
+        count: usize,
+        root: CValue,
+    ) -> CResult<&Self> {
+        let (all, part) = self.mpi_parts(&send, &recv, count)?;
+        self.mpi_collective("MPI_Scatter", all, part, count, root)
+    }
+
+    /// collect `count` elements of `send` from every rank into `recv` on
+    /// `root`, which has to hold `count` elements for every rank
+    pub fn mpi_gather(
+        &self,
+        send: Variable,
+        recv: Variable,
+        count: usize,
+        root: CValue,
+    ) -> CResult<&Self> {
+        let (all, part) = self.mpi_parts(&recv, &send, count)?;
+        self.mpi_collective("MPI_Gather", part, all, count, root)
+    }
+
+    /// the buffers of the root and of one rank, both of the same datatype
+    /// and holding at least `count` elements
+    fn mpi_parts(
+        &self,
+        all: &Variable,
+        part: &Variable,
+        count: usize,
+    ) -> CResult<(Buffer, Buffer)> {
+        if all == part {
+            return Err(CodegenError::AliasedBuffers(all.clone()));
+        }
+        let (all_buffer, part_buffer) = (self.mpi_buffer(all)?, self.mpi_buffer(part)?);
+        if all_buffer.datatype != part_buffer.datatype {
+            let ty = self.variables.lock().unwrap()[part].clone();
+            return Err(CodegenError::UnsupportedType {
+                ty,
+                dialect: self.dialect,
+            });
+        }
+        for (name, buffer) in [(all, &all_buffer), (part, &part_buffer)] {
+            if buffer.count < count {
+                return Err(CodegenError::MessageOverflow {
+                    variable: name.clone(),
+                    count,
+                });
+            }
+        }
+        Ok((all_buffer, part_buffer))
+    }
+
+    /// scatter and gather move `count` elements per rank
+    fn mpi_collective(
+        &self,
+        function: &str,
+        send: Buffer,
+        recv: Buffer,
+        count: usize,
+        root: CValue,
+    ) -> CResult<&Self> {
+        let root = root.to_c(self.dialect, self)?;
+        self.mpi_call(
+            function,
+            vec![
+                send.address,
+                count.to_string(),
+                send.datatype,
+                recv.address,
+                count.to_string(),
+                recv.datatype,
+                root,
+                "MPI_COMM_WORLD".to_string(),
+            ],
+        )
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::c_cg::c_value::{CLiteral, IntegerSuffix, Radix};
+
+    fn int(v: i128) -> CValue {
+        CValue::Literal(CLiteral::Int(v, IntegerSuffix::None, Radix::Decimal))
+    }
+
+    #[test]
+    fn test_mpi_collectives() {
+        let context = Context::parallel("m".to_string());
+        let array = |size| CType::Array {
+            ty: Box::new(CType::F64),
+            size: Some(size),
+        };
+        context
+            .def(
+                "solve".to_string(),
+                CType::Void,
+                vec![
+                    (array(16), "all".to_string()),
+                    (array(4), "part".to_string()),
+                    (CType::F64, "local".to_string()),
+                    (CType::F64, "total".to_string()),
+                ],
+                |c| {
+                    c.mpi_init(None)?
+                        .mpi_comm_rank("rank".to_string())?
+                        .mpi_scatter("all".to_string(), "part".to_string(), 4, int(0))?
+                        .mpi_allreduce("local".to_string(), "total".to_string(), ReductionOp::Add)?
+                        .mpi_allreduce("total".to_string(), "total".to_string(), ReductionOp::Max)?
+                        .mpi_gather("part".to_string(), "all".to_string(), 4, int(0))?
+                        .mpi_finalize()?;
+                    Ok(c)
+                },
+            )
+            .unwrap();
+        assert!(
+            context
+                .def("bad".to_string(), CType::Void, vec![], |c| {
+                    c.mpi_bcast("missing".to_string(), int(0))?;
+                    Ok(c)
+                })
+                .is_err()
+        );
+        let bad = |body: fn(&Context) -> CResult<&Context>| {
+            context
+                .def(
+                    "bad".to_string(),
+                    CType::Void,
+                    vec![
+                        (array(16), "all".to_string()),
+                        (array(4), "part".to_string()),
+                        (CType::F64, "local".to_string()),
+                        (CType::F64, "total".to_string()),
+                    ],
+                    |c| {
+                        body(&c)?;
+                        Ok(c)
+                    },
+                )
+                .err()
+        };
+        assert_eq!(
+            bad(|c| c.mpi_scatter("all".to_string(), "part".to_string(), 5, int(0))),
+            Some(CodegenError::MessageOverflow {
+                variable: "part".to_string(),
+                count: 5
+            })
+        );
+        assert_eq!(
+            bad(|c| c.mpi_allreduce(
+                "local".to_string(),
+                "total".to_string(),
+                ReductionOp::BitXor
+            )),
+            Some(CodegenError::InvalidReduction {
+                op: ReductionOp::BitXor,
+                ty: CType::F64
+            })
+        );
+        // the operator is checked once the send buffer is known
+        assert_eq!(
+            bad(|c| c.mpi_allreduce(
+                "missing".to_string(),
+                "total".to_string(),
+                ReductionOp::BitXor
+            )),
+            Some(CodegenError::UnknownVariable("missing".to_string()))
+        );
+        assert_eq!(
+            bad(|c| c.mpi_reduce(
+                "local".to_string(),
+                "local".to_string(),
+                ReductionOp::Add,
+                int(0)
+            )),
+            Some(CodegenError::AliasedBuffers("local".to_string()))
+        );
+        assert_eq!(
+            bad(|c| c.mpi_gather("part".to_string(), "part".to_string(), 4, int(0))),
+            Some(CodegenError::AliasedBuffers("part".to_string()))
+        );
+        assert_eq!(
+            bad(|c| c.mpi_comm_rank("local".to_string())),
+            Some(CodegenError::UnsupportedType {
+                ty: CType::F64,
+                dialect: CDialect::Parallel
+            })
+        );
+        let c_file = context.c_file.lock().unwrap();
+        assert_eq!(
+            c_file.functions[0].body,
+            "MPI_Init(NULL, NULL);\n\
+             int32_t m_MM_rank;\n\
+             MPI_Comm_rank(MPI_COMM_WORLD, &m_MM_rank);\n\
+             MPI_Scatter(m_MM_all, 4, MPI_DOUBLE, m_MM_part, 4, MPI_DOUBLE, 0, MPI_COMM_WORLD);\n\
+             MPI_Allreduce(&m_MM_local, &m_MM_total, 1, MPI_DOUBLE, MPI_SUM, MPI_COMM_WORLD);\n\
+             MPI_Allreduce(MPI_IN_PLACE, &m_MM_total, 1, MPI_DOUBLE, MPI_MAX, MPI_COMM_WORLD);\n\
+             MPI_Gather(m_MM_part, 4, MPI_DOUBLE, m_MM_all, 4, MPI_DOUBLE, 0, MPI_COMM_WORLD);\n\
+             MPI_Finalize();\n"
+        );
+        assert!(c_file.includes.contains("<mpi.h>"));
+    }
+
+    #[test]
+    fn test_mpi_struct_datatype() {
+        let context = Context::parallel("m".to_string());
+        let particle = CType::Struct {
+            name: Some("particle".to_string()),
+            repr: None,
+            fields: vec![
+                (
+                    "position".to_string(),
+                    CType::Array {
+                        ty: Box::new(CType::F32),
+                        size: Some(3),
+                    },
+                ),
+                ("id".to_string(), CType::U64),
+            ],
+        };
+        context
+            .def(
+                "share".to_string(),
+                CType::Void,
+                vec![(particle.clone(), "p".to_string())],
+                |c| {
+                    c.mpi_bcast("p".to_string(), int(0))?;
+                    c.mpi_send("p".to_string(), int(1), int(7))?;
+                    Ok(c)
+                },
+            )
+            .unwrap();
+        // no predefined operator applies to a derived datatype
+        assert_eq!(
+            context
+                .def(
+                    "sum".to_string(),
+                    CType::Void,
+                    vec![(particle.clone(), "p".to_string())],
+                    |c| {
+                        c.mpi_allreduce("p".to_string(), "p".to_string(), ReductionOp::Add)?;
+                        Ok(c)
+                    },
+                )
+                .err(),
+            Some(CodegenError::InvalidReduction {
+                op: ReductionOp::Add,
+                ty: particle.clone()
+            })
+        );
+        let c_file = context.c_file.lock().unwrap();
+        let datatype = c_file.mpi_datatypes[&particle].clone();
+        assert_eq!(c_file.functions.len(), 2);
+        assert_eq!(
+            c_file.functions[0].body,
+            "static MPI_Datatype datatype = MPI_DATATYPE_NULL;\n\
+             if (datatype == MPI_DATATYPE_NULL) {\n\
+             int lengths[2] = { 3, 1 };\n\
+             MPI_Aint offsets[2] = { offsetof(struct m_MM_particle, position), offsetof(struct m_MM_particle, id) };\n\
+             MPI_Datatype types[2] = { MPI_FLOAT, MPI_UINT64_T };\n\
+             MPI_Datatype packed;\n\
+             MPI_Type_create_struct(2, lengths, offsets, types, &packed);\n\
+             MPI_Type_create_resized(packed, 0, sizeof(struct m_MM_particle), &datatype);\n\
+             MPI_Type_free(&packed);\n\
+             MPI_Type_commit(&datatype);\n\
+             }\n\
+             return datatype;\n"
+        );
+        assert_eq!(
+            c_file.functions[1].body,
+            format!(
+                "MPI_Bcast(&m_MM_p, 1, {d}, 0, MPI_COMM_WORLD);\n\
+                 MPI_Send(&m_MM_p, 1, {d}, 1, 7, MPI_COMM_WORLD);\n",
+                d = datatype
+            )
+        );
+    }
+}
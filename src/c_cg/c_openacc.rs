@@ -0,0 +1,323 @@
+use super::{
+    CDialect,
+    c_error::{CResult, CodegenError},
+    c_openmp::ReductionOp,
+    c_stmt::{Context, Variable},
+    c_type::CType,
+    c_value::CValue,
+};
+
+/// data clauses, arrays are moved whole as `a[0:N]` sections
+#[derive(Debug, Clone, Default)]
+pub struct AccData {
+    pub copy: Vec<Variable>,
+    pub copyin: Vec<Variable>,
+    pub copyout: Vec<Variable>,
+    pub create: Vec<Variable>,
+    pub present: Vec<Variable>,
+}
+
+/// loop clauses of `acc parallel loop`
+#[derive(Debug, Clone, Default)]
+pub struct AccLoop {
+    pub gang: bool,
+    pub vector: bool,
+    pub independent: bool,
+    pub collapse: Option<usize>,
+    pub reduction: Vec<(ReductionOp, Variable)>,
+}
+
+/// `[0:N]` sections of every dimension of `ty`, empty for scalars
+fn sections(variable: &Variable, ty: &CType) -> CResult<String> {
+    match ty {
+        CType::Array {
+            ty: element,
+            size: Some(size),
+        } => Ok(format!("[0:{}]{}", size, sections(variable, element)?)),
+        CType::Const { ty } => sections(variable, ty),
+        // the extent behind a pointer is not part of its type
+        CType::Array { size: None, .. } | CType::Pointer { .. } => Err(CodegenError::UnsizedData {
+            variable: variable.clone(),
+            ty: ty.clone(),
+        }),
+        _ => Ok("".to_string()),
+    }
+}
+
+impl Context {
+    /// check the dialect and make the file require OpenACC
+    fn require_openacc(&self) -> CResult<()> {
+        if self.dialect != CDialect::Parallel {
+            return Err(CodegenError::UnsupportedFeature {
+                feature: "OpenACC",
+                dialect: self.dialect,
+            });
+        }
+        let guard = "#if !defined(_OPENACC)\n#error \"compile with -fopenacc\"\n#endif";
+        let mut c_file = self.c_file.lock().unwrap();
+        c_file.compiler_flags.insert("-fopenacc".to_string());
+        c_file.preamble(guard);
+        Ok(())
+    }
+
+    /// `clause(a[0:N], b)`, `None` without variables
+    fn acc_data_list(&self, clause: &str, names: &[Variable]) -> CResult<Option<String>> {
+        if names.is_empty() {
+            return Ok(None);
+        }
+        let names = names
+            .iter()
+            .map(|name| {
+                let c_name = self.known_variable(name)?;
+                let ty = self.variables.lock().unwrap()[name].clone();
+                Ok(format!("{}{}", c_name, sections(name, &ty)?))
+            })
+            .collect::<CResult<Vec<_>>>()?;
+        Ok(Some(format!("{}({})", clause, names.join(", "))))
+    }
+
+    fn acc_data_clauses(&self, data: &AccData) -> CResult<Vec<String>> {
+        let mut out = vec![];
+        out.extend(self.acc_data_list("copy", &data.copy)?);
+        out.extend(self.acc_data_list("copyin", &data.copyin)?);
+        out.extend(self.acc_data_list("copyout", &data.copyout)?);
+        out.extend(self.acc_data_list("create", &data.create)?);
+        out.extend(self.acc_data_list("present", &data.present)?);
+        Ok(out)
+    }
+
+    fn acc_pragma(&self, directive: &str, clauses: Vec<String>) -> CResult<()> {
+        self.require_openacc()?;
+        let clauses: String = clauses.iter().map(|c| format!(" {}", c)).collect();
+        self.current_source
+            .lock()
+            .unwrap()
+            .push_str(&format!("#pragma acc {}{}\n", directive, clauses));
+        Ok(())
+    }
+
+    /// `#pragma acc parallel loop` over `Context::for_loop`, the body cannot
+    /// leave the compute region
+    pub fn acc_parallel_loop(
+        &self,
+        data: AccData,
+        clauses: AccLoop,
+        init: Option<(CType, String, CValue)>,
+        condition: Option<CValue>,
+        step: Option<CValue>,
+        block: impl Fn(Self) -> CResult<(Self, Variable)>,
+    ) -> CResult<&Self> {
+        let mut out = self.acc_data_clauses(&data)?;
+        if clauses.gang {
+            out.push("gang".to_string());
+        }
+        if clauses.vector {
+            out.push("vector".to_string());
+        }
+        if clauses.independent {
+            out.push("independent".to_string());
+        }
+        out.extend(clauses.collapse.map(|n| format!("collapse({})", n)));
+        for (op, name) in &clauses.reduction {
+            out.push(format!(
+                "reduction({}: {})",
+                op.spelling(),
+                self.known_variable(name)?
+            ));
+        }
+        self.acc_pragma("parallel loop", out)?;
+        self.directive_loop(clauses.collapse, init, condition, step, block)
+    }
+
+    /// a region the compiler parallelizes on its own
+    pub fn acc_kernels(
+        &self,
+        data: AccData,
+        block: impl Fn(Self) -> CResult<Self>,
+    ) -> CResult<&Self> {
+        let clauses = self.acc_data_clauses(&data)?;
+        self.acc_pragma("kernels", clauses)?;
+        self.directive_block(block)
+    }
+
+    /// keep data on the device for the whole block
+    pub fn acc_data(&self, data: AccData, block: impl Fn(Self) -> CResult<Self>) -> CResult<&Self> {
+        let clauses = self.acc_data_clauses(&data)?;
+        self.acc_pragma("data", clauses)?;
+        self.directive_block(block)
+    }
+
+    /// copy `host` back from the device and `device` to it inside a data region
+    pub fn acc_update(&self, host: Vec<Variable>, device: Vec<Variable>) -> CResult<&Self> {
+        let mut clauses = vec![];
+        clauses.extend(self.acc_data_list("host", &host)?);
+        clauses.extend(self.acc_data_list("device", &device)?);
+        if clauses.is_empty() {
+            return Err(CodegenError::UnsupportedFeature {
+                feature: "acc update without variables",
+                dialect: self.dialect,
+            });
+        }
+        self.acc_pragma("update", clauses)?;
+        Ok(self)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::c_cg::c_value::{BinaryOp, CLiteral, FloatSuffix, IntegerSuffix, Radix, UnaryOp};
+
+    fn int(v: i128) -> CValue {
+        CValue::Literal(CLiteral::Int(v, IntegerSuffix::None, Radix::Decimal))
+    }
+
+    fn var(name: &str) -> CValue {
+        CValue::Variable(name.to_string())
+    }
+
+    #[test]
+    fn test_acc_data_region() {
+        let context = Context::parallel("m".to_string());
+        let matrix = CType::Array {
+            ty: Box::new(CType::Array {
+                ty: Box::new(CType::F32),
+                size: Some(8),
+            }),
+            size: Some(4),
+        };
+        context
+            .def(
+                "sum".to_string(),
+                CType::Void,
+                vec![(matrix, "a".to_string())],
+                |c| {
+                    c.set(
+                        CType::F32,
+                        "s".to_string(),
+                        CValue::Literal(CLiteral::Float(0.5, FloatSuffix::F32)),
+                    )?;
+                    c.acc_data(
+                        AccData {
+                            copyin: vec!["a".to_string()],
+                            ..Default::default()
+                        },
+                        |c| {
+                            c.acc_parallel_loop(
+                                AccData {
+                                    present: vec!["a".to_string()],
+                                    ..Default::default()
+                                },
+                                AccLoop {
+                                    gang: true,
+                                    reduction: vec![(ReductionOp::Add, "s".to_string())],
+                                    ..Default::default()
+                                },
+                                Some((CType::I32, "i".to_string(), int(0))),
+                                Some(CValue::BinOp(
+                                    BinaryOp::Lt,
+                                    Box::new(var("i")),
+                                    Box::new(int(4)),
+                                )),
+                                Some(CValue::UnaryOp(UnaryOp::PostInc, Box::new(var("i")))),
+                                |c| Ok((c, "i".to_string())),
+                            )?;
+                            c.acc_update(vec![], vec!["a".to_string()])?;
+                            Ok(c)
+                        },
+                    )?;
+                    Ok(c)
+                },
+            )
+            .unwrap();
+        assert_eq!(
+            context.render(),
+            "#include <stdint.h>\n\
+             \n\
+             #if !defined(_OPENACC)\n\
+             #error \"compile with -fopenacc\"\n\
+             #endif\n\
+             \n\
+             void m_MM_sum(float m_MM_a[4][8]);\n\
+             \n\
+             void m_MM_sum(float m_MM_a[4][8]) {\n\
+             float m_MM_s = 0.5f;\n\
+             #pragma acc data copyin(m_MM_a[0:4][0:8])\n\
+             {\n\
+             #pragma acc parallel loop present(m_MM_a[0:4][0:8]) gang reduction(+: m_MM_s)\n\
+             for(int32_t m_MM_i = 0; m_MM_i < 4; m_MM_i++) {\n\
+             }\n\
+             #pragma acc update device(m_MM_a[0:4][0:8])\n\
+             }\n\
+             }\n"
+        );
+        assert_eq!(context.compiler_flags(), vec!["-fopenacc".to_string()]);
+    }
+
+    #[test]
+    fn test_acc_unsized_data() {
+        let context = Context::parallel("m".to_string());
+        let pointer = CType::Pointer {
+            ty: Box::new(CType::F64),
+        };
+        context
+            .variables
+            .lock()
+            .unwrap()
+            .insert("p".to_string(), pointer.clone());
+        let data = AccData {
+            copy: vec!["p".to_string()],
+            ..Default::default()
+        };
+        assert_eq!(
+            context.acc_kernels(data, Ok).err(),
+            Some(CodegenError::UnsizedData {
+                variable: "p".to_string(),
+                ty: pointer,
+            })
+        );
+    }
+
+    #[test]
+    fn test_acc_loop_body() {
+        let context = Context::parallel("m".to_string());
+        // a function with the label `out` around an `acc parallel loop`
+        let acc_loop = |body: fn(&Context) -> CResult<&Context>| {
+            context
+                .def("f".to_string(), CType::Void, vec![], |c| {
+                    c.acc_parallel_loop(
+                        Default::default(),
+                        Default::default(),
+                        Some((CType::I32, "i".to_string(), int(0))),
+                        Some(CValue::BinOp(
+                            BinaryOp::Lt,
+                            Box::new(var("i")),
+                            Box::new(int(4)),
+                        )),
+                        Some(CValue::UnaryOp(UnaryOp::PostInc, Box::new(var("i")))),
+                        |c| {
+                            body(&c)?;
+                            Ok((c, "i".to_string()))
+                        },
+                    )?
+                    .label("out".to_string())?;
+                    Ok(c)
+                })
+                .err()
+        };
+        // nothing but `continue` leaves the compute region
+        assert_eq!(acc_loop(|c| c.continue_loop()), None);
+        assert_eq!(
+            acc_loop(|c| c.ret(None)),
+            Some(CodegenError::MisplacedJump("return"))
+        );
+        assert_eq!(
+            acc_loop(|c| c.break_loop()),
+            Some(CodegenError::MisplacedJump("break"))
+        );
+        assert_eq!(
+            acc_loop(|c| c.goto("out".to_string())),
+            Some(CodegenError::UndefinedLabel("out".to_string()))
+        );
+    }
+}
@@ -0,0 +1,313 @@
+use std::sync::{Arc, Mutex};
+
+use super::{
+    CDialect, KernelTarget, ToC,
+    c_error::{CResult, CodegenError},
+    c_file::{CFile, CFunction, Visibility},
+    c_stmt::{Context, Variable},
+    c_type::{AddressSpace, CType, OpenCLType},
+    c_value::{CLiteral, CValue, IntegerSuffix, Radix},
+};
+
+const OPENCL: CDialect = CDialect::Kernel(KernelTarget::OpenCL);
+
+/// OpenCL C has fixed size integers under their C names and no `<stdint.h>`
+fn scalar(ty: &CType) -> Option<&'static str> {
+    Some(match ty {
+        CType::Bool => "bool",
+        CType::I8 => "char",
+        CType::U8 => "uchar",
+        CType::I16 => "short",
+        CType::U16 => "ushort",
+        CType::I32 => "int",
+        CType::U32 => "uint",
+        CType::I64 => "long",
+        CType::U64 => "ulong",
+        CType::F32 => "float",
+        CType::F64 => "double",
+        _ => return None,
+    })
+}
+
+/// OpenCL C spelling of `ty`, `None` for the types printed the same way as
+/// in C, any type OpenCL C does not have is an error
+pub(crate) fn opencl_type(
+    ty: &CType,
+    dialect: CDialect,
+    context: &Context,
+) -> CResult<Option<String>> {
+    let unsupported = || CodegenError::UnsupportedType {
+        ty: ty.clone(),
+        dialect,
+    };
+    match ty {
+        // doubles are optional before OpenCL C 3.0
+        CType::F64 => {
+            enable_fp64(context);
+            Ok(Some("double".to_string()))
+        }
+        CType::OpenCLExtension(OpenCLType::Vector { ty: element, size }) => {
+            if !matches!(size, 2 | 3 | 4 | 8 | 16) || matches!(**element, CType::Bool) {
+                return Err(unsupported());
+            }
+            if matches!(**element, CType::F64) {
+                enable_fp64(context);
+            }
+            let element = scalar(element).ok_or_else(unsupported)?;
+            Ok(Some(format!("{}{}", element, size)))
+        }
+        CType::ModernCExtension(_) | CType::UniformCallBack => Err(unsupported()),
+        _ => Ok(scalar(ty).map(str::to_string)),
+    }
+}
+
+fn enable_fp64(context: &Context) {
+    context
+        .c_file
+        .lock()
+        .unwrap()
+        .preamble("#pragma OPENCL EXTENSION cl_khr_fp64 : enable");
+}
+
+impl ToC for AddressSpace {
+    fn to_c(&self, dialect: CDialect, _context: &Context) -> CResult<String> {
+        if dialect != OPENCL {
+            return Err(CodegenError::UnsupportedFeature {
+                feature: "address spaces",
+                dialect,
+            });
+        }
+        Ok(match self {
+            AddressSpace::Global => "__global",
+            AddressSpace::Local => "__local",
+            AddressSpace::Constant => "__constant",
+            AddressSpace::Private => "__private",
+        }
+        .to_string())
+    }
+}
+
+/// work-item functions, each takes the dimension `0..=2` it is queried for
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum WorkItem {
+    GlobalId,
+    LocalId,
+    GroupId,
+    GlobalSize,
+    LocalSize,
+    NumGroups,
+}
+
+impl WorkItem {
+    /// `get_global_id(dim)`, ..., a `size_t`
+    pub fn call(self, dim: usize) -> CValue {
+        let name = match self {
+            WorkItem::GlobalId => "get_global_id",
+            WorkItem::LocalId => "get_local_id",
+            WorkItem::GroupId => "get_group_id",
+            WorkItem::GlobalSize => "get_global_size",
+            WorkItem::LocalSize => "get_local_size",
+            WorkItem::NumGroups => "get_num_groups",
+        };
+        CValue::FunctionCall(
+            Box::new(CValue::Extern(name.to_string())),
+            vec![CValue::Literal(CLiteral::Int(
+                dim as i128,
+                IntegerSuffix::U,
+                Radix::Decimal,
+            ))],
+        )
+    }
+}
+
+/// kernel pointer arguments must point to `__global`, `__constant` or
+/// `__local` memory, either side of a `const` qualifier
+fn check_kernel_argument(ty: &CType) -> CResult<()> {
+    let pointee = match ty {
+        CType::Pointer { ty } => match ty.as_ref() {
+            CType::Const { ty } => ty,
+            _ => ty,
+        },
+        CType::Const { ty } => return check_kernel_argument(ty),
+        _ => return Ok(()),
+    };
+    match pointee.as_ref() {
+        CType::OpenCLExtension(OpenCLType::AddressSpace { space, .. })
+            if *space != AddressSpace::Private =>
+        {
+            Ok(())
+        }
+        _ => Err(CodegenError::UnsupportedType {
+            ty: ty.clone(),
+            dialect: OPENCL,
+        }),
+    }
+}
+
+impl Context {
+    /// a context generating an OpenCL C program
+    pub fn opencl(module_name: String) -> Self {
+        let c_file = CFile {
+            dialect: OPENCL,
+            ..Default::default()
+        };
+        Self {
+            c_file: Arc::new(Mutex::new(c_file)),
+            module: module_name,
+            dialect: OPENCL,
+            variables: Default::default(),
+            current_source: Default::default(),
+            last_statement: Default::default(),
+            scopes: Default::default(),
+            jumps: Default::default(),
+            device: false,
+        }
+    }
+
+    fn check_opencl(&self, feature: &'static str) -> CResult<()> {
+        if self.dialect != OPENCL {
+            return Err(CodegenError::UnsupportedFeature {
+                feature,
+                dialect: self.dialect,
+            });
+        }
+        Ok(())
+    }
+
+    /// define a `__kernel void` entry point, enqueued from the host by name
+    pub fn def_kernel(
+        &self,
+        name: Variable,
+        args: Vec<(CType, Variable)>,
+        body: impl Fn(Self) -> CResult<Self>,
+    ) -> CResult<&Self> {
+        self.check_opencl("kernels")?;
+        for (ty, _) in &args {
+            check_kernel_argument(ty)?;
+        }
+        let name = self.c_name(&name);
+        let (prototype, body) = self.function(&name, CType::Void, args, body)?;
+        self.c_file.lock().unwrap().functions.push(CFunction {
+            prototype: format!("__kernel {}", prototype),
+            body,
+            visibility: Visibility::Exported,
+            entry_point: false,
+        });
+        Ok(self)
+    }
+
+    /// `barrier(CLK_LOCAL_MEM_FENCE)`, or the global fence if `global`
+    pub fn barrier(&self, global: bool) -> CResult<&Self> {
+        self.check_opencl("work-group barriers")?;
+        let fence = if global {
+            "CLK_GLOBAL_MEM_FENCE"
+        } else {
+            "CLK_LOCAL_MEM_FENCE"
+        };
+        self.current_source
+            .lock()
+            .unwrap()
+            .push_str(&format!("barrier({});\n", fence));
+        Ok(self)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn global(ty: CType) -> CType {
+        CType::Pointer {
+            ty: Box::new(CType::OpenCLExtension(OpenCLType::AddressSpace {
+                space: AddressSpace::Global,
+                ty: Box::new(ty),
+            })),
+        }
+    }
+
+    #[test]
+    fn test_opencl_types() {
+        let context = Context::opencl("m".to_string());
+        let to_c = |ty: CType| ty.to_c(OPENCL, &context);
+        let vector = |ty, size| {
+            CType::OpenCLExtension(OpenCLType::Vector {
+                ty: Box::new(ty),
+                size,
+            })
+        };
+        assert_eq!(to_c(CType::U64).unwrap(), "ulong");
+        assert_eq!(to_c(vector(CType::F32, 4)).unwrap(), "float4");
+        assert_eq!(to_c(vector(CType::U8, 16)).unwrap(), "uchar16");
+        assert!(to_c(vector(CType::F32, 5)).is_err());
+        assert_eq!(
+            to_c(global(CType::Const {
+                ty: Box::new(CType::F32)
+            }))
+            .unwrap(),
+            "__global const float *"
+        );
+        assert!(
+            to_c(CType::FunctionPointer {
+                return_ty: Box::new(CType::Void),
+                arguments: vec![],
+            })
+            .is_err()
+        );
+    }
+
+    #[test]
+    fn test_opencl_kernel() {
+        let context = Context::opencl("m".to_string());
+        context
+            .def_kernel(
+                "scale".to_string(),
+                vec![
+                    (global(CType::F64), "xs".to_string()),
+                    (CType::F32, "k".to_string()),
+                    (
+                        CType::Pointer {
+                            ty: Box::new(CType::Const {
+                                ty: Box::new(CType::OpenCLExtension(OpenCLType::AddressSpace {
+                                    space: AddressSpace::Global,
+                                    ty: Box::new(CType::F32),
+                                })),
+                            }),
+                        },
+                        "ys".to_string(),
+                    ),
+                ],
+                |c| {
+                    c.set(CType::U64, "i".to_string(), WorkItem::GlobalId.call(0))?;
+                    c.barrier(false)?;
+                    Ok(c)
+                },
+            )
+            .unwrap();
+        // private pointers cannot be kernel arguments
+        assert!(
+            context
+                .def_kernel(
+                    "bad".to_string(),
+                    vec![(
+                        CType::Pointer {
+                            ty: Box::new(CType::F32)
+                        },
+                        "xs".to_string()
+                    )],
+                    Ok,
+                )
+                .is_err()
+        );
+        assert_eq!(
+            context.render(),
+            "#pragma OPENCL EXTENSION cl_khr_fp64 : enable\n\
+             \n\
+             __kernel void m_MM_scale(__global double *m_MM_xs, float m_MM_k, const __global float *m_MM_ys);\n\
+             \n\
+             __kernel void m_MM_scale(__global double *m_MM_xs, float m_MM_k, const __global float *m_MM_ys) {\n\
+             ulong m_MM_i = get_global_id(0u);\n\
+             barrier(CLK_LOCAL_MEM_FENCE);\n\
+             }\n"
+        );
+    }
+}
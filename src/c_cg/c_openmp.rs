@@ -0,0 +1,597 @@
+use std::sync::{Arc, Mutex};
+
+use super::{
+    CDialect, ToC,
+    c_error::{CResult, CodegenError},
+    c_stmt::{Context, JumpTargets, Labels, Variable},
+    c_type::CType,
+    c_value::{BinaryOp, CValue, UnaryOp},
+};
+
+/// operator of a `reduction` clause
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ReductionOp {
+    Add,
+    Mul,
+    BitAnd,
+    BitOr,
+    BitXor,
+    And,
+    Or,
+    Min,
+    Max,
+}
+
+impl ReductionOp {
+    /// spelling in `reduction` clauses, shared by OpenMP and OpenACC
+    pub(crate) fn spelling(self) -> &'static str {
+        match self {
+            ReductionOp::Add => "+",
+            ReductionOp::Mul => "*",
+            ReductionOp::BitAnd => "&",
+            ReductionOp::BitOr => "|",
+            ReductionOp::BitXor => "^",
+            ReductionOp::And => "&&",
+            ReductionOp::Or => "||",
+            ReductionOp::Min => "min",
+            ReductionOp::Max => "max",
+        }
+    }
+}
+
+/// loop schedule, with an optional chunk size
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Schedule {
+    Static(Option<usize>),
+    Dynamic(Option<usize>),
+    Guided(Option<usize>),
+    Auto,
+    Runtime,
+}
+
+impl Schedule {
+    fn to_omp(self) -> String {
+        let (kind, chunk) = match self {
+            Schedule::Static(chunk) => ("static", chunk),
+            Schedule::Dynamic(chunk) => ("dynamic", chunk),
+            Schedule::Guided(chunk) => ("guided", chunk),
+            Schedule::Auto => ("auto", None),
+            Schedule::Runtime => ("runtime", None),
+        };
+        match chunk {
+            Some(chunk) => format!("schedule({}, {})", kind, chunk),
+            None => format!("schedule({})", kind),
+        }
+    }
+}
+
+/// data sharing and loop clauses of a directive, every variable must be
+/// in scope where the directive is emitted
+#[derive(Debug, Clone, Default)]
+pub struct OmpClauses {
+    pub private: Vec<Variable>,
+    pub shared: Vec<Variable>,
+    pub reduction: Vec<(ReductionOp, Variable)>,
+    pub schedule: Option<Schedule>,
+    pub collapse: Option<usize>,
+}
+
+/// loops of worksharing and simd directives must be in the canonical form
+/// `for(T var = lb; var op ub; incr)`, with an integer or object pointer `var`
+/// compared by `<`, `<=`, `>`, `>=` or `!=` and moved by `++`, `--`,
+/// `+=`, `-=` or `var = var +/- step`
+fn check_canonical_loop(
+    init: &Option<(CType, String, CValue)>,
+    condition: &Option<CValue>,
+    step: &Option<CValue>,
+) -> CResult<()> {
+    use BinaryOp::*;
+    let Some((ty, name, _)) = init else {
+        return Err(CodegenError::NonCanonicalLoop("init"));
+    };
+    let integer = matches!(
+        ty,
+        CType::I8
+            | CType::I16
+            | CType::I32
+            | CType::I64
+            | CType::U8
+            | CType::U16
+            | CType::U32
+            | CType::U64
+    );
+    if !integer && !matches!(ty, CType::Pointer { .. }) {
+        return Err(CodegenError::NonCanonicalLoop("init"));
+    }
+    let is_var = |v: &CValue| matches!(v, CValue::Variable(v) if v == name);
+    match condition {
+        Some(CValue::BinOp(Lt | Le | Gt | Ge | Ne, a, b)) if is_var(a) != is_var(b) => {}
+        _ => return Err(CodegenError::NonCanonicalLoop("condition")),
+    }
+    match step {
+        Some(CValue::UnaryOp(
+            UnaryOp::PreInc | UnaryOp::PostInc | UnaryOp::PreDec | UnaryOp::PostDec,
+            v,
+        )) if is_var(v) => Ok(()),
+        Some(CValue::BinOp(AddAssign | SubAssign, v, _)) if is_var(v) => Ok(()),
+        Some(CValue::BinOp(Assign, v, value)) if is_var(v) => match value.as_ref() {
+            CValue::BinOp(Add, a, b) if is_var(a) || is_var(b) => Ok(()),
+            CValue::BinOp(Sub, a, _) if is_var(a) => Ok(()),
+            _ => Err(CodegenError::NonCanonicalLoop("step")),
+        },
+        _ => Err(CodegenError::NonCanonicalLoop("step")),
+    }
+}
+
+impl Context {
+    /// a context generating C with OpenMP, OpenACC or MPI directives
+    pub fn parallel(module_name: String) -> Self {
+        Self {
+            c_file: Arc::new(Mutex::new(Default::default())),
+            module: module_name,
+            dialect: CDialect::Parallel,
+            variables: Default::default(),
+            current_source: Default::default(),
+            last_statement: Default::default(),
+            scopes: Default::default(),
+            jumps: Default::default(),
+            device: false,
+        }
+    }
+
+    /// check the dialect and make the file require OpenMP
+    fn require_openmp(&self) -> CResult<()> {
+        if self.dialect != CDialect::Parallel {
+            return Err(CodegenError::UnsupportedFeature {
+                feature: "OpenMP",
+                dialect: self.dialect,
+            });
+        }
+        let guard = "#if !defined(_OPENMP)\n#error \"compile with -fopenmp\"\n#endif";
+        let mut c_file = self.c_file.lock().unwrap();
+        c_file.include("omp.h");
+        c_file.compiler_flags.insert("-fopenmp".to_string());
+        c_file.preamble(guard);
+        Ok(())
+    }
+
+    /// escaped name of `name`, which has to be in scope
+    pub(crate) fn known_variable(&self, name: &Variable) -> CResult<String> {
+        if !self.variables.lock().unwrap().contains_key(name) {
+            return Err(CodegenError::UnknownVariable(name.clone()));
+        }
+        Ok(self.c_name(name))
+    }
+
+    fn variable_list(&self, clause: &str, names: &[Variable]) -> CResult<Option<String>> {
+        if names.is_empty() {
+            return Ok(None);
+        }
+        let names = names
+            .iter()
+            .map(|name| self.known_variable(name))
+            .collect::<CResult<Vec<_>>>()?;
+        Ok(Some(format!("{}({})", clause, names.join(", "))))
+    }
+
+    fn omp_clauses(&self, clauses: &OmpClauses) -> CResult<String> {
+        let mut out = vec![];
+        out.extend(self.variable_list("private", &clauses.private)?);
+        out.extend(self.variable_list("shared", &clauses.shared)?);
+        for (op, name) in &clauses.reduction {
+            out.push(format!(
+                "reduction({}: {})",
+                op.spelling(),
+                self.known_variable(name)?
+            ));
+        }
+        out.extend(clauses.schedule.map(Schedule::to_omp));
+        out.extend(clauses.collapse.map(|n| format!("collapse({})", n)));
+        Ok(out.iter().map(|c| format!(" {}", c)).collect())
+    }
+
+    fn omp_pragma(&self, directive: &str, clauses: &OmpClauses) -> CResult<()> {
+        self.require_openmp()?;
+        let clauses = self.omp_clauses(clauses)?;
+        self.current_source
+            .lock()
+            .unwrap()
+            .push_str(&format!("#pragma omp {}{}\n", directive, clauses));
+        Ok(())
+    }
+
+    fn omp_block(
+        &self,
+        directive: &str,
+        clauses: &OmpClauses,
+        block: impl Fn(Self) -> CResult<Self>,
+    ) -> CResult<&Self> {
+        self.omp_pragma(directive, clauses)?;
+        self.directive_block(block)
+    }
+
+    /// the structured block following a directive, no jump may leave it
+    pub(crate) fn directive_block(&self, block: impl Fn(Self) -> CResult<Self>) -> CResult<&Self> {
+        let mut s = self.scope();
+        let labels = Arc::new(Mutex::new(Labels::default()));
+        s.jumps = JumpTargets {
+            labels: Some(labels.clone()),
+            ..Default::default()
+        };
+        let block = block(s)?;
+        labels.lock().unwrap().check()?;
+        let block = block.current_source.lock().unwrap().clone();
+        self.current_source
+            .lock()
+            .unwrap()
+            .push_str(&format!("{{\n{}}}\n", block));
+        Ok(self)
+    }
+
+    /// the loop nest following a loop directive, its body may only end an
+    /// iteration with `continue` and `collapse(n)` needs `n` perfectly
+    /// nested loops
+    pub(crate) fn directive_loop(
+        &self,
+        collapse: Option<usize>,
+        init: Option<(CType, String, CValue)>,
+        condition: Option<CValue>,
+        step: Option<CValue>,
+        block: impl Fn(Self) -> CResult<(Self, Variable)>,
+    ) -> CResult<&Self> {
+        let labels = Arc::new(Mutex::new(Labels::default()));
+        let jumps = JumpTargets {
+            in_directive_loop: true,
+            labels: Some(labels.clone()),
+            ..Default::default()
+        };
+        let depth = self.for_loop_in(jumps, init, condition, step, block)?;
+        labels.lock().unwrap().check()?;
+        match collapse {
+            Some(collapse) if collapse == 0 || collapse > depth => {
+                Err(CodegenError::CollapseDepth { collapse, depth })
+            }
+            _ => Ok(self),
+        }
+    }
+
+    /// `#pragma omp parallel for` over `Context::for_loop`
+    pub fn omp_parallel_for(
+        &self,
+        clauses: OmpClauses,
+        init: Option<(CType, String, CValue)>,
+        condition: Option<CValue>,
+        step: Option<CValue>,
+        block: impl Fn(Self) -> CResult<(Self, Variable)>,
+    ) -> CResult<&Self> {
+        check_canonical_loop(&init, &condition, &step)?;
+        self.omp_pragma("parallel for", &clauses)?;
+        self.directive_loop(clauses.collapse, init, condition, step, block)
+    }
+
+    /// `#pragma omp simd`, vectorize the loop on the current thread
+    pub fn omp_simd(
+        &self,
+        clauses: OmpClauses,
+        init: Option<(CType, String, CValue)>,
+        condition: Option<CValue>,
+        step: Option<CValue>,
+        block: impl Fn(Self) -> CResult<(Self, Variable)>,
+    ) -> CResult<&Self> {
+        if clauses.schedule.is_some() {
+            return Err(CodegenError::UnsupportedFeature {
+                feature: "schedule on omp simd",
+                dialect: self.dialect,
+            });
+        }
+        check_canonical_loop(&init, &condition, &step)?;
+        self.omp_pragma("simd", &clauses)?;
+        self.directive_loop(clauses.collapse, init, condition, step, block)
+    }
+
+    /// a block run by one thread at a time, `name` distinguishes
+    /// unrelated critical sections
+    pub fn omp_critical(
+        &self,
+        name: Option<&str>,
+        block: impl Fn(Self) -> CResult<Self>,
+    ) -> CResult<&Self> {
+        let directive = match name {
+            Some(name) => format!("critical({})", self.c_name(name)),
+            None => "critical".to_string(),
+        };
+        self.omp_block(&directive, &OmpClauses::default(), block)
+    }
+
+    /// `#pragma omp atomic` on `name op= value`
+    pub fn omp_atomic(&self, name: Variable, op: BinaryOp, value: CValue) -> CResult<&Self> {
+        let Some(op) = op.compound_assignment() else {
+            return Err(CodegenError::UnsupportedFeature {
+                feature: "atomic operator",
+                dialect: self.dialect,
+            });
+        };
+        self.known_variable(&name)?;
+        self.omp_pragma("atomic", &OmpClauses::default())?;
+        let update = CValue::BinOp(op, Box::new(CValue::Variable(name)), Box::new(value));
+        let update = update.to_c(self.dialect, self)?;
+        self.current_source
+            .lock()
+            .unwrap()
+            .push_str(&format!("{};\n", update));
+        Ok(self)
+    }
+
+    pub fn omp_barrier(&self) -> CResult<&Self> {
+        self.omp_pragma("barrier", &OmpClauses::default())?;
+        Ok(self)
+    }
+
+    /// a deferred task, only `private` and `shared` apply to tasks
+    pub fn omp_task(
+        &self,
+        clauses: OmpClauses,
+        block: impl Fn(Self) -> CResult<Self>,
+    ) -> CResult<&Self> {
+        if !clauses.reduction.is_empty() || clauses.schedule.is_some() || clauses.collapse.is_some()
+        {
+            return Err(CodegenError::UnsupportedFeature {
+                feature: "loop clauses on omp task",
+                dialect: self.dialect,
+            });
+        }
+        self.omp_block("task", &clauses, block)
+    }
+
+    /// wait for the child tasks of the current task
+    pub fn omp_taskwait(&self) -> CResult<&Self> {
+        self.omp_pragma("taskwait", &OmpClauses::default())?;
+        Ok(self)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::c_cg::c_value::{CLiteral, IntegerSuffix, Radix, UnaryOp};
+
+    fn int(v: i128) -> CValue {
+        CValue::Literal(CLiteral::Int(v, IntegerSuffix::None, Radix::Decimal))
+    }
+
+    fn var(name: &str) -> CValue {
+        CValue::Variable(name.to_string())
+    }
+
+    #[test]
+    fn test_omp_parallel_for() {
+        let context = Context::parallel("m".to_string());
+        context
+            .def("total".to_string(), CType::I32, vec![], |c| {
+                c.set(CType::I32, "sum".to_string(), int(0))?
+                    .omp_parallel_for(
+                        OmpClauses {
+                            reduction: vec![(ReductionOp::Add, "sum".to_string())],
+                            schedule: Some(Schedule::Static(Some(4))),
+                            ..Default::default()
+                        },
+                        Some((CType::I32, "i".to_string(), int(0))),
+                        Some(CValue::BinOp(
+                            BinaryOp::Lt,
+                            Box::new(var("i")),
+                            Box::new(int(8)),
+                        )),
+                        Some(CValue::UnaryOp(UnaryOp::PostInc, Box::new(var("i")))),
+                        |c| {
+                            c.omp_atomic("sum".to_string(), BinaryOp::Add, var("i"))?;
+                            Ok((c, "i".to_string()))
+                        },
+                    )?
+                    .omp_critical(Some("io"), Ok)?
+                    .omp_barrier()?
+                    .ret(Some(var("sum")))?;
+                Ok(c)
+            })
+            .unwrap();
+        // compiles with `gcc -fopenmp -c`
+        assert_eq!(
+            context.render(),
+            "#include <omp.h>\n\
+             #include <stdint.h>\n\
+             \n\
+             #if !defined(_OPENMP)\n\
+             #error \"compile with -fopenmp\"\n\
+             #endif\n\
+             \n\
+             int32_t m_MM_total(void);\n\
+             \n\
+             int32_t m_MM_total(void) {\n\
+             int32_t m_MM_sum = 0;\n\
+             #pragma omp parallel for reduction(+: m_MM_sum) schedule(static, 4)\n\
+             for(int32_t m_MM_i = 0; m_MM_i < 8; m_MM_i++) {\n\
+             #pragma omp atomic\n\
+             m_MM_sum += m_MM_i;\n\
+             }\n\
+             #pragma omp critical(m_MM_io)\n\
+             {\n\
+             }\n\
+             #pragma omp barrier\n\
+             return m_MM_sum;\n\
+             }\n"
+        );
+        assert_eq!(context.compiler_flags(), vec!["-fopenmp".to_string()]);
+    }
+
+    #[test]
+    fn test_omp_errors() {
+        let context = Context::parallel("m".to_string());
+        let private = OmpClauses {
+            private: vec!["missing".to_string()],
+            ..Default::default()
+        };
+        assert_eq!(
+            context.omp_task(private, Ok).err(),
+            Some(CodegenError::UnknownVariable("missing".to_string()))
+        );
+        assert!(Context::standard("m".to_string()).omp_taskwait().is_err());
+        // loops must be in canonical form
+        let init = || Some((CType::I32, "i".to_string(), int(0)));
+        let lt = || {
+            Some(CValue::BinOp(
+                BinaryOp::Lt,
+                Box::new(var("i")),
+                Box::new(int(8)),
+            ))
+        };
+        let step = |value| Some(CValue::BinOp(BinaryOp::Assign, Box::new(var("i")), value));
+        let omp_for = |init, condition, step| {
+            context
+                .omp_parallel_for(Default::default(), init, condition, step, |c| {
+                    Ok((c, "i".to_string()))
+                })
+                .err()
+        };
+        assert_eq!(
+            omp_for(init(), lt(), None),
+            Some(CodegenError::NonCanonicalLoop("step"))
+        );
+        assert_eq!(
+            omp_for(init(), lt(), step(Box::new(int(1)))),
+            Some(CodegenError::NonCanonicalLoop("step"))
+        );
+        assert_eq!(
+            omp_for(
+                init(),
+                Some(CValue::BinOp(
+                    BinaryOp::Eq,
+                    Box::new(var("i")),
+                    Box::new(int(8))
+                )),
+                Some(CValue::UnaryOp(UnaryOp::PostInc, Box::new(var("i"))))
+            ),
+            Some(CodegenError::NonCanonicalLoop("condition"))
+        );
+        assert_eq!(
+            omp_for(
+                Some((CType::F32, "i".to_string(), int(0))),
+                lt(),
+                Some(CValue::UnaryOp(UnaryOp::PostInc, Box::new(var("i"))))
+            ),
+            Some(CodegenError::NonCanonicalLoop("init"))
+        );
+        assert_eq!(
+            omp_for(
+                init(),
+                lt(),
+                step(Box::new(CValue::BinOp(
+                    BinaryOp::Add,
+                    Box::new(int(2)),
+                    Box::new(var("i"))
+                )))
+            ),
+            None
+        );
+        let fn_pointer = CType::FunctionPointer {
+            return_ty: Box::new(CType::Void),
+            arguments: vec![],
+        };
+        assert_eq!(
+            omp_for(
+                Some((fn_pointer, "i".to_string(), int(0))),
+                lt(),
+                Some(CValue::UnaryOp(UnaryOp::PostInc, Box::new(var("i"))))
+            ),
+            Some(CodegenError::NonCanonicalLoop("init"))
+        );
+    }
+
+    /// `for(int32_t j = 0; j < 4; j++) {}`
+    fn inner_loop(c: &Context) -> CResult<&Context> {
+        c.for_loop(
+            Some((CType::I32, "j".to_string(), int(0))),
+            Some(CValue::BinOp(
+                BinaryOp::Lt,
+                Box::new(var("j")),
+                Box::new(int(4)),
+            )),
+            Some(CValue::UnaryOp(UnaryOp::PostInc, Box::new(var("j")))),
+            |c| Ok((c, "j".to_string())),
+        )
+    }
+
+    #[test]
+    fn test_omp_loop_body() {
+        let context = Context::parallel("m".to_string());
+        // a function with the label `out` around an `omp parallel for`
+        let omp_for = |collapse, body: fn(&Context) -> CResult<&Context>| {
+            context
+                .def("f".to_string(), CType::Void, vec![], |c| {
+                    c.omp_parallel_for(
+                        OmpClauses {
+                            collapse,
+                            ..Default::default()
+                        },
+                        Some((CType::I32, "i".to_string(), int(0))),
+                        Some(CValue::BinOp(
+                            BinaryOp::Lt,
+                            Box::new(var("i")),
+                            Box::new(int(8)),
+                        )),
+                        Some(CValue::UnaryOp(UnaryOp::PostInc, Box::new(var("i")))),
+                        |c| {
+                            body(&c)?;
+                            Ok((c, "i".to_string()))
+                        },
+                    )?
+                    .label("out".to_string())?;
+                    Ok(c)
+                })
+                .err()
+        };
+        // nothing but `continue` leaves the structured block
+        assert_eq!(omp_for(None, |c| c.continue_loop()), None);
+        assert_eq!(
+            omp_for(None, |c| c.ret(None)),
+            Some(CodegenError::MisplacedJump("return"))
+        );
+        assert_eq!(
+            omp_for(None, |c| c.break_loop()),
+            Some(CodegenError::MisplacedJump("break"))
+        );
+        assert_eq!(
+            omp_for(None, |c| c.goto("out".to_string())),
+            Some(CodegenError::UndefinedLabel("out".to_string()))
+        );
+        // but loops inside it can still be left
+        assert_eq!(
+            omp_for(None, |c| c.for_loop(None, None, None, |c| {
+                c.break_loop()?;
+                Ok((c, "i".to_string()))
+            })),
+            None
+        );
+        // `collapse` counts the perfectly nested loops
+        assert_eq!(omp_for(Some(2), inner_loop), None);
+        assert_eq!(
+            omp_for(Some(2), |c| c.continue_loop()),
+            Some(CodegenError::CollapseDepth {
+                collapse: 2,
+                depth: 1
+            })
+        );
+        assert_eq!(
+            omp_for(Some(2), |c| inner_loop(c)?.continue_loop()),
+            Some(CodegenError::CollapseDepth {
+                collapse: 2,
+                depth: 1
+            })
+        );
+        assert_eq!(
+            omp_for(Some(3), inner_loop),
+            Some(CodegenError::CollapseDepth {
+                collapse: 3,
+                depth: 2
+            })
+        );
+    }
+}
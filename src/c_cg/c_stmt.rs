@@ -1,22 +1,108 @@
-use core::panic;
 use std::{
-    collections::BTreeSet,
+    collections::{BTreeMap, BTreeSet},
+    ops::Range,
     sync::{Arc, Mutex},
 };
 
 use crate::escape::{
-    format_to_escape_replace, get_temp_variable_name, string_to_escape_to_c_ansi_id,
+    format_to_escape_replace, get_temp_variable, string_to_escape_to_c_ansi_id,
+    string_to_escape_to_glsl_id,
 };
 
-use super::{CDialect, ToC, c_arch::Arch, c_file::CFile, c_type::CType, c_value::CValue};
+use super::{
+    CDialect, KernelTarget, ToC,
+    c_arch::{Arch, DataModel},
+    c_error::{CResult, CodegenError},
+    c_file::{CFile, CFunction, Visibility},
+    c_type::{CType, tag_enumerator},
+    c_value::CValue,
+};
 pub type Variable = String;
+/// a block builder receiving a nested context and the `phi` variable
+pub type PhiBuilder = Box<dyn Fn(Context, Variable) -> CResult<Context>>;
+/// a match arm builder receiving a nested context, the `phi` variable and
+/// the variable bound to the payload of the matched variant, `None` for a
+/// `Void` variant
+pub type MatchBuilder = Box<dyn Fn(Context, Variable, Option<Variable>) -> CResult<Context>>;
+
+/// an arm of `Context::switch`
+pub struct SwitchCase {
+    /// the `case` labels of the arm, integer constant expressions
+    pub labels: Vec<CValue>,
+    /// run into the next arm instead of ending with `break`
+    pub fallthrough: bool,
+    pub block: PhiBuilder,
+}
+
+/// statements the jump statements of a context can leave
+#[derive(Debug, Clone, Default)]
+pub struct JumpTargets {
+    /// return type of the enclosing function, `None` outside of functions
+    pub function: Option<CType>,
+    pub in_loop: bool,
+    pub in_switch: bool,
+    /// the body of an OpenMP or OpenACC loop, which only `continue` leaves
+    pub in_directive_loop: bool,
+    /// labels of the enclosing function, shared by its nested contexts,
+    /// `None` outside of functions
+    pub labels: Option<Arc<Mutex<Labels>>>,
+}
+
+/// statements later code looks back at, see `Context::trailing_statement`
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum Statement {
+    /// a `for` loop starting `depth` perfectly nested loops
+    Loop(usize),
+    /// `return`, `break`, `continue` or `goto`, code after it is unreachable
+    Jump,
+}
+
+/// the variables declared so far in a `{ ... }` scope
+pub(crate) type Scope = Arc<Mutex<Vec<Variable>>>;
+
+/// labels defined in a function and the ones `goto` jumps to
+#[derive(Debug, Default)]
+pub struct Labels {
+    /// each label with the scopes enclosing it and how many variables each
+    /// had declared before it
+    defined: BTreeMap<Variable, Vec<(Scope, usize)>>,
+    targets: BTreeSet<Variable>,
+}
+
+/// the end of the `switch` arm `s`, `break` unless it already jumps away
+fn end_arm(s: &Context) -> &'static str {
+    if s.ends_in_jump() {
+        "}\n"
+    } else {
+        "break;\n}\n"
+    }
+}
+
+impl Labels {
+    /// every `goto` has to jump to a label of the same function
+    pub(crate) fn check(&self) -> CResult<()> {
+        match self.targets.iter().find(|t| !self.defined.contains_key(*t)) {
+            Some(label) => Err(CodegenError::UndefinedLabel(label.clone())),
+            None => Ok(()),
+        }
+    }
+}
 
 pub struct Context {
     pub c_file: Arc<Mutex<CFile>>,
     pub module: String,
     pub dialect: CDialect,
-    pub variables: Mutex<BTreeSet<Variable>>,
+    /// variables in scope and the type they were declared with
+    pub variables: Mutex<BTreeMap<Variable, CType>>,
     pub current_source: Mutex<String>,
+    /// the last statement of `current_source` worth remembering and the
+    /// part of the source it spans
+    pub(crate) last_statement: Mutex<Option<(Statement, Range<usize>)>>,
+    /// the enclosing scopes of the function, innermost last
+    pub(crate) scopes: Vec<Scope>,
+    pub jumps: JumpTargets,
+    /// inside a CUDA `__global__` or `__device__` function
+    pub device: bool,
 }
 
 impl Context {
@@ -27,22 +113,72 @@ impl Context {
             dialect: CDialect::Standard,
             variables: Default::default(),
             current_source: Default::default(),
+            last_statement: Default::default(),
+            scopes: Default::default(),
+            jumps: Default::default(),
+            device: false,
         }
     }
 }
 
 impl Context {
+    /// print integer types as builtin types of `model` instead of the
+    /// `<stdint.h>` fixed width types
+    pub fn with_data_model(self, model: DataModel) -> Self {
+        self.c_file.lock().unwrap().data_model = Some(model);
+        self
+    }
+
+    /// render the translation unit, statements emitted directly on this
+    /// context are placed after the other globals
+    pub fn render(&self) -> String {
+        let top_level = self.current_source.lock().unwrap().clone();
+        self.c_file.lock().unwrap().render_with(&top_level)
+    }
+
+    /// render a `(header, source)` pair, see `CFile::render_header`
+    pub fn render_split(&self, header_name: &str) -> (String, String) {
+        let top_level = self.current_source.lock().unwrap().clone();
+        let c_file = self.c_file.lock().unwrap();
+        (
+            c_file.render_header(header_name),
+            c_file.render_source_with(header_name, &top_level),
+        )
+    }
+
+    pub fn include(&self, header: &str) -> &Self {
+        self.c_file.lock().unwrap().include(header);
+        self
+    }
+
+    /// flags the rendered file has to be compiled with
+    pub fn compiler_flags(&self) -> Vec<String> {
+        let c_file = self.c_file.lock().unwrap();
+        c_file.compiler_flags.iter().cloned().collect()
+    }
+
     pub fn global_inline_c(&self, code: String) -> &Self {
         self.c_file.lock().unwrap().global_inline_c.push(code);
         self
     }
 
+    pub fn forward_declaration(&self, code: String) -> &Self {
+        self.c_file.lock().unwrap().forward_declaration(code);
+        self
+    }
+
+    pub fn type_definition(&self, code: String) -> &Self {
+        self.c_file.lock().unwrap().type_definitions.push(code);
+        self
+    }
+
     pub fn local_inline_c(self, code: String) -> Self {
         let code = format!("do {{{}}} while(0);\n", code);
         self.current_source.lock().unwrap().push_str(&code);
         self
     }
 
+    #[allow(clippy::too_many_arguments)]
     pub fn inline_asm(
         &self,
         arch: Arch,
@@ -52,12 +188,15 @@ impl Context {
         outputs: Vec<Variable>,
         input_output: Vec<Variable>,
         changed_registers: Vec<String>,
-    ) -> &Self {
+    ) -> CResult<&Self> {
         if self.dialect != CDialect::Standard {
-            panic!("inline asm is not supported in dialect {:?}", self.dialect);
+            return Err(CodegenError::UnsupportedFeature {
+                feature: "inline asm",
+                dialect: self.dialect,
+            });
         }
 
-        let arch = format!("#ifdef {}", arch.to_c(self.dialect, self).unwrap());
+        let arch = format!("#ifdef {}", arch.to_c(self.dialect, self)?);
         let code = code
             .into_iter()
             .map(|x| format!("\"{}\"", x))
@@ -117,7 +256,7 @@ impl Context {
             mapping
         );
         self.current_source.lock().unwrap().push_str(&asm);
-        self
+        Ok(self)
     }
 
     // pub fn decl(&self, name: String, ty: CType, value: CValue) -> &Self {
@@ -131,68 +270,193 @@ impl Context {
     //     self
     // }
 
-    pub fn set(&self, ty: CType, name: Variable, value: CValue) -> &Self {
-        if self.variables.lock().unwrap().insert(name.clone()) {
-            let name = string_to_escape_to_c_ansi_id(&self.module, &name);
-            let ty = ty.to_c(self.dialect, self).unwrap();
-            let value = value.to_c(self.dialect, self).unwrap();
+    /// declare and initialize `name`, or assign to it if it is in scope
+    pub fn set(&self, ty: CType, name: Variable, value: CValue) -> CResult<&Self> {
+        if !self.variables.lock().unwrap().contains_key(&name) {
+            self.declared(&name)?;
+            let c_name = self.c_name(&name);
+            let declaration = ty.declare(&c_name, self.dialect, self)?;
+            let value = value.to_c(self.dialect, self)?;
+            // only a declared variable is in scope
+            self.variables.lock().unwrap().insert(name, ty);
             self.current_source
                 .lock()
                 .unwrap()
-                .push_str(&format!("{} {} = {};\n", ty, name, value));
+                .push_str(&format!("{} = {};\n", declaration, value));
         } else {
-            let name = string_to_escape_to_c_ansi_id(&self.module, &name);
-            self.current_source.lock().unwrap().push_str(&format!(
-                "{} = {};\n",
-                name,
-                value.to_c(self.dialect, self).unwrap()
-            ));
+            let name = self.c_name(&name);
+            let value = value.to_c(self.dialect, self)?;
+            self.current_source
+                .lock()
+                .unwrap()
+                .push_str(&format!("{} = {};\n", name, value));
         }
 
-        self
+        Ok(self)
     }
 
-    fn decl_tmp(&self, ty: &CType) -> (&Self, String) {
-        let name = get_temp_variable_name(&self.module);
-        self.current_source.lock().unwrap().push_str(&format!(
-            "{} {};\n",
-            ty.to_c(self.dialect, self).unwrap(),
-            name.clone()
-        ));
-        (self, name)
+    /// record `name` as declared in the innermost scope
+    ///
+    /// CUDA is C++, which forbids jumping over a declaration, so there a
+    /// declaration cannot follow a `goto` to a label not defined yet
+    fn declared(&self, name: &Variable) -> CResult<()> {
+        if self.dialect == CDialect::Kernel(KernelTarget::Cuda) && self.forward_goto_pending() {
+            return Err(CodegenError::JumpOverDeclaration(name.clone()));
+        }
+        if let Some(scope) = self.scopes.last() {
+            scope.lock().unwrap().push(name.clone());
+        }
+        Ok(())
     }
 
-    pub fn block(&self, ty: &CType, block: impl Fn(Self, Variable) -> Self) -> &Self {
-        let (_, ret) = self.decl_tmp(ty);
-        let s = block(
-            Context {
-                c_file: self.c_file.clone(),
-                dialect: self.dialect,
-                module: self.module.clone(),
-                variables: Mutex::new(self.variables.lock().unwrap().clone()),
-                current_source: Mutex::new(String::new()),
-            },
-            ret.clone(),
-        );
+    /// a CUDA `goto` to a label defined before cannot enter a scope the
+    /// `goto` is not in past one of its declarations
+    fn check_backward_goto(&self, labels: &Labels, name: &Variable) -> CResult<()> {
+        if self.dialect != CDialect::Kernel(KernelTarget::Cuda) {
+            return Ok(());
+        }
+        let Some(scopes) = labels.defined.get(name) else {
+            return Ok(());
+        };
+        for (scope, declared) in scopes {
+            if *declared > 0 && !self.scopes.iter().any(|s| Arc::ptr_eq(s, scope)) {
+                let variable = scope.lock().unwrap()[0].clone();
+                return Err(CodegenError::JumpOverDeclaration(variable));
+            }
+        }
+        Ok(())
+    }
+
+    /// whether a `goto` of the function targets a label not defined yet
+    fn forward_goto_pending(&self) -> bool {
+        self.jumps.labels.as_ref().is_some_and(|labels| {
+            let labels = labels.lock().unwrap();
+            labels
+                .targets
+                .iter()
+                .any(|t| !labels.defined.contains_key(t))
+        })
+    }
+
+    /// escaped name of `name` in this module, GLSL reserves every
+    /// identifier containing `__` and gets an escape without them
+    pub fn c_name(&self, name: &str) -> String {
+        if self.dialect == CDialect::Kernel(KernelTarget::GLSL) {
+            string_to_escape_to_glsl_id(&self.module, name)
+        } else {
+            string_to_escape_to_c_ansi_id(&self.module, name)
+        }
+    }
+
+    /// escaped name of a fresh temporary
+    pub fn temp_name(&self) -> String {
+        self.c_name(&get_temp_variable())
+    }
+
+    /// a nested context sharing the file, seeing the variables of this one
+    pub(crate) fn scope(&self) -> Self {
+        self.scope_with(self.variables.lock().unwrap().clone())
+    }
+
+    fn scope_with(&self, variables: BTreeMap<Variable, CType>) -> Self {
+        Context {
+            c_file: self.c_file.clone(),
+            dialect: self.dialect,
+            module: self.module.clone(),
+            variables: Mutex::new(variables),
+            current_source: Mutex::new(String::new()),
+            last_statement: Default::default(),
+            scopes: self
+                .scopes
+                .iter()
+                .cloned()
+                .chain(Some(Default::default()))
+                .collect(),
+            jumps: self.jumps.clone(),
+            device: self.device,
+        }
+    }
+
+    /// append `code`, remembering it as `statement`
+    fn push_statement(&self, code: &str, statement: Statement) {
+        let mut source = self.current_source.lock().unwrap();
+        let start = source.len();
+        source.push_str(code);
+        *self.last_statement.lock().unwrap() = Some((statement, start..source.len()));
+    }
+
+    /// the last statement if nothing was emitted after it, and whether it
+    /// is the only statement of the context
+    pub(crate) fn trailing_statement(&self) -> Option<(Statement, bool)> {
+        let end = self.current_source.lock().unwrap().len();
+        match &*self.last_statement.lock().unwrap() {
+            Some((statement, range)) if range.end == end => Some((*statement, range.start == 0)),
+            _ => None,
+        }
+    }
+
+    /// whether the last statement leaves the context, so a `break` after
+    /// it would be dead code
+    fn ends_in_jump(&self) -> bool {
+        matches!(self.trailing_statement(), Some((Statement::Jump, _)))
+    }
+
+    /// a nested context for the body of a loop
+    fn loop_scope(&self, variables: BTreeMap<Variable, CType>) -> Self {
+        let mut s = self.scope_with(variables);
+        s.jumps.in_loop = true;
+        s
+    }
+
+    /// declare a fresh variable, nothing is declared for `void` so that
+    /// statements without a value can still take a `phi`
+    fn decl_tmp(&self, ty: &CType) -> CResult<(&Self, Variable)> {
+        let name = get_temp_variable();
+        if *ty == CType::Void {
+            return Ok((self, name));
+        }
+        self.declared(&name)?;
+        let declaration = ty.declare(&self.c_name(&name), self.dialect, self)?;
+        self.variables
+            .lock()
+            .unwrap()
+            .insert(name.clone(), ty.clone());
+        self.current_source
+            .lock()
+            .unwrap()
+            .push_str(&format!("{};\n", declaration));
+        Ok((self, name))
+    }
+
+    pub fn block(
+        &self,
+        ty: &CType,
+        block: impl Fn(Self, Variable) -> CResult<Self>,
+    ) -> CResult<&Self> {
+        let (_, ret) = self.decl_tmp(ty)?;
+        let s = block(self.scope(), ret.clone())?;
         let block = s.current_source.lock().unwrap().clone();
         self.current_source
             .lock()
             .unwrap()
-            .push_str(format!("{{\n{}\n}}", block).as_str());
-        self
+            .push_str(format!("{{\n{}}}\n", block).as_str());
+        Ok(self)
     }
 
     /// variable in pragma should escape with {variable}
-    pub fn raw_pragma(&self, mut code: String) -> &Self {
-        if self.dialect != CDialect::Standard {
-            panic!("raw pragma is not supported in dialect {:?}", self.dialect);
+    pub fn raw_pragma(&self, mut code: String) -> CResult<&Self> {
+        if !matches!(self.dialect, CDialect::Standard | CDialect::Parallel) {
+            return Err(CodegenError::UnsupportedFeature {
+                feature: "raw pragma",
+                dialect: self.dialect,
+            });
         }
         code = format_to_escape_replace(&self.module, code);
         self.current_source
             .lock()
             .unwrap()
             .push_str(&format!("#pragma {}\n", code));
-        self
+        Ok(self)
     }
 
     // if else if else
@@ -201,54 +465,123 @@ impl Context {
         &self,
         ty: &CType,
         conds: Vec<CValue>,
-        builder: Vec<Box<dyn Fn(Self, Variable) -> Self>>,
-        otherwise: impl Fn(Self, Variable) -> Self,
-    ) -> &Self {
+        builder: Vec<PhiBuilder>,
+        otherwise: impl Fn(Self, Variable) -> CResult<Self>,
+    ) -> CResult<&Self> {
         if conds.is_empty() || builder.len() != conds.len() || builder.is_empty() {
-            panic!(
-                "malformed cond expecting at least one condition and one block, got {} conditions and {} blocks",
-                conds.len(),
-                builder.len()
-            );
-        } else {
-            let mut code = String::new();
-            let (_, phi) = self.decl_tmp(ty);
-            for (i, (cond, block)) in conds.into_iter().zip(builder.into_iter()).enumerate() {
-                let s = block(
-                    Context {
-                        c_file: self.c_file.clone(),
-                        dialect: self.dialect,
-                        module: self.module.clone(),
-                        variables: Mutex::new(self.variables.lock().unwrap().clone()),
-                        current_source: Mutex::new(String::new()),
-                    },
-                    phi.clone(),
-                );
-                let block = s.current_source.lock().unwrap().clone();
-
-                let cond = cond.to_c(self.dialect, &s).unwrap();
-                if i == 0 {
-                    code.push_str(&format!("if({}) {{\n{}\n", cond, block));
-                } else {
-                    code.push_str(&format!("}} else if({}) {{\n{}\n", cond, block));
-                }
+            return Err(CodegenError::MalformedCond {
+                conditions: conds.len(),
+                blocks: builder.len(),
+            });
+        }
+
+        let mut code = String::new();
+        let (_, phi) = self.decl_tmp(ty)?;
+        for (i, (cond, block)) in conds.into_iter().zip(builder).enumerate() {
+            let s = block(self.scope(), phi.clone())?;
+            let block = s.current_source.lock().unwrap().clone();
+
+            let cond = cond.to_c(self.dialect, &s)?;
+            if i == 0 {
+                code.push_str(&format!("if({}) {{\n{}\n", cond, block));
+            } else {
+                code.push_str(&format!("}} else if({}) {{\n{}\n", cond, block));
             }
+        }
 
-            let s = otherwise(
-                Context {
-                    c_file: self.c_file.clone(),
-                    dialect: self.dialect,
-                    module: self.module.clone(),
-                    variables: Mutex::new(self.variables.lock().unwrap().clone()),
-                    current_source: Mutex::new(String::new()),
-                },
-                phi.clone(),
-            );
+        let s = otherwise(self.scope(), phi.clone())?;
+        let block = s.current_source.lock().unwrap().clone();
+        code.push_str(&format!("}} else {{\n{}\n}}\n", block));
+        self.current_source.lock().unwrap().push_str(&code);
+        Ok(self)
+    }
+
+    /// `switch` on the tag of a `CType::TaggedUnion` value
+    ///
+    /// each arm names a different variant, its payload is copied into a
+    /// fresh variable passed to the builder; without `otherwise` the arms
+    /// must cover every variant
+    pub fn match_tagged(
+        &self,
+        ty: &CType,
+        union_ty: &CType,
+        scrutinee: CValue,
+        arms: Vec<(String, MatchBuilder)>,
+        otherwise: Option<PhiBuilder>,
+    ) -> CResult<&Self> {
+        let CType::TaggedUnion { name, variants } = union_ty else {
+            return Err(CodegenError::NotATaggedUnion(union_ty.clone()));
+        };
+        for (i, (variant, _)) in arms.iter().enumerate() {
+            if !variants.iter().any(|(v, _)| v == variant) {
+                return Err(CodegenError::UnknownVariant {
+                    ty: name.clone(),
+                    variant: variant.clone(),
+                });
+            }
+            // a second arm would be a duplicate `case` label
+            if arms[..i].iter().any(|(v, _)| v == variant) {
+                return Err(CodegenError::DuplicateArm {
+                    ty: name.clone(),
+                    variant: variant.clone(),
+                });
+            }
+        }
+        if otherwise.is_none() {
+            let missing = variants
+                .iter()
+                .filter(|(v, _)| !arms.iter().any(|(arm, _)| arm == v))
+                .map(|(v, _)| v.clone())
+                .collect::<Vec<_>>();
+            if !missing.is_empty() {
+                return Err(CodegenError::NonExhaustiveMatch {
+                    ty: name.clone(),
+                    missing,
+                });
+            }
+        }
+
+        let subject = get_temp_variable();
+        self.set(union_ty.clone(), subject.clone(), scrutinee)?;
+        let c_subject = self.c_name(&subject);
+        let (_, phi) = self.decl_tmp(ty)?;
+
+        let mut code = format!("switch({}.tag) {{\n", c_subject);
+        for (variant, block) in arms {
+            let payload_ty = &variants.iter().find(|(v, _)| *v == variant).unwrap().1;
+            let mut s = self.scope();
+            s.jumps.in_switch = true;
+            let payload = (*payload_ty != CType::Void).then(get_temp_variable);
+            if let Some(payload) = &payload {
+                s.set(
+                    payload_ty.clone(),
+                    payload.clone(),
+                    CValue::MemberAccess(
+                        Box::new(CValue::MemberAccess(
+                            Box::new(CValue::Variable(subject.clone())),
+                            "payload".to_string(),
+                        )),
+                        variant.clone(),
+                    ),
+                )?;
+            }
+            let s = block(s, phi.clone(), payload)?;
+            let block = s.current_source.lock().unwrap().clone();
+            code.push_str(&format!(
+                "case {}: {{\n{}{}",
+                tag_enumerator(&self.module, name, &variant),
+                block,
+                end_arm(&s)
+            ));
+        }
+        if let Some(otherwise) = otherwise {
+            let s = otherwise(self.scope(), phi.clone())?;
             let block = s.current_source.lock().unwrap().clone();
-            code.push_str(&format!("}} else {{\n{}\n}}\n", block));
-            s.current_source.lock().unwrap().push_str(&code);
-            self
+            code.push_str(&format!("default: {{\n{}{}", block, end_arm(&s)));
         }
+        code.push_str("}\n");
+        self.current_source.lock().unwrap().push_str(&code);
+        Ok(self)
     }
 
     pub fn for_loop(
@@ -257,65 +590,936 @@ impl Context {
         condition: Option<CValue>,
         step: Option<CValue>,
 
-        block: impl Fn(Self) -> (Self, Variable),
-    ) -> &Self {
-        let init = init
-            .map(|(ty, name, value)| {
-                let name = string_to_escape_to_c_ansi_id(&self.module, &name);
-                let ty = ty.to_c(self.dialect, self).unwrap();
-                let value = value.to_c(self.dialect, self).unwrap();
-                format!("{} {} = {};", ty, name, value)
-            })
-            .unwrap_or(";".to_string());
-        let condition = condition
-            .map(|c| c.to_c(self.dialect, self).unwrap())
-            .unwrap_or(";".to_string());
-        let step = step
-            .map(|c| c.to_c(self.dialect, self).unwrap())
-            .unwrap_or("".to_string());
-        let (block, _) = block(Context {
-            c_file: self.c_file.clone(),
-            dialect: self.dialect,
-            module: self.module.clone(),
-            variables: Mutex::new(self.variables.lock().unwrap().clone()),
-            current_source: Mutex::new(String::new()),
-        });
+        block: impl Fn(Self) -> CResult<(Self, Variable)>,
+    ) -> CResult<&Self> {
+        let jumps = JumpTargets {
+            in_loop: true,
+            ..self.jumps.clone()
+        };
+        self.for_loop_in(jumps, init, condition, step, block)?;
+        Ok(self)
+    }
+
+    /// `for_loop` with the body leaving through `jumps`, returns the depth
+    /// of the perfect loop nest it starts
+    pub(crate) fn for_loop_in(
+        &self,
+        jumps: JumpTargets,
+        init: Option<(CType, String, CValue)>,
+        condition: Option<CValue>,
+        step: Option<CValue>,
+        block: impl Fn(Self) -> CResult<(Self, Variable)>,
+    ) -> CResult<usize> {
+        let mut variables = self.variables.lock().unwrap().clone();
+        let declared = init.as_ref().map(|(_, name, _)| name.clone());
+        let init = match init {
+            Some((ty, name, value)) => {
+                variables.insert(name.clone(), ty.clone());
+                let name = self.c_name(&name);
+                let declaration = ty.declare(&name, self.dialect, self)?;
+                let value = value.to_c(self.dialect, self)?;
+                format!("{} = {};", declaration, value)
+            }
+            None => ";".to_string(),
+        };
+        let condition = match condition {
+            Some(c) => format!("{};", c.to_c(self.dialect, self)?),
+            None => ";".to_string(),
+        };
+        let step = match step {
+            Some(c) => c.to_c(self.dialect, self)?,
+            None => "".to_string(),
+        };
+        let mut s = self.scope_with(variables);
+        // the variable of the init clause is scoped to the loop
+        if let Some(name) = &declared {
+            s.declared(name)?;
+        }
+        s.jumps = jumps;
+        let (block, _) = block(s)?;
+        let depth = match block.trailing_statement() {
+            Some((Statement::Loop(depth), true)) => depth + 1,
+            _ => 1,
+        };
         let block = block.current_source.lock().unwrap().clone();
-        let code = format!("for({}{}{}) {{{}}}", init, condition, step, block);
+        let code = format!("for({} {} {}) {{\n{}}}\n", init, condition, step, block);
+        self.push_statement(&code, Statement::Loop(depth));
+        Ok(depth)
+    }
+
+    /// `while(condition) { ... }`, in `block` the function param is a new
+    /// context and the `phi` variable
+    pub fn while_loop(
+        &self,
+        ty: &CType,
+        condition: CValue,
+        block: impl Fn(Self, Variable) -> CResult<Self>,
+    ) -> CResult<&Self> {
+        let (_, phi) = self.decl_tmp(ty)?;
+        let condition = condition.to_c(self.dialect, self)?;
+        let s = block(self.loop_scope(self.variables.lock().unwrap().clone()), phi)?;
+        let block = s.current_source.lock().unwrap().clone();
+        self.current_source
+            .lock()
+            .unwrap()
+            .push_str(&format!("while({}) {{\n{}}}\n", condition, block));
+        Ok(self)
+    }
+
+    /// `do { ... } while(condition);`, the body runs at least once and
+    /// its variables are not visible in `condition`
+    pub fn do_while(
+        &self,
+        ty: &CType,
+        condition: CValue,
+        block: impl Fn(Self, Variable) -> CResult<Self>,
+    ) -> CResult<&Self> {
+        let (_, phi) = self.decl_tmp(ty)?;
+        let condition = condition.to_c(self.dialect, self)?;
+        let s = block(self.loop_scope(self.variables.lock().unwrap().clone()), phi)?;
+        let block = s.current_source.lock().unwrap().clone();
+        self.current_source
+            .lock()
+            .unwrap()
+            .push_str(&format!("do {{\n{}}} while({});\n", block, condition));
+        Ok(self)
+    }
+
+    /// `switch` on an integer `scrutinee`
+    ///
+    /// every arm ends with `break` unless it falls through into the next
+    /// one, `otherwise` becomes the `default` arm after the cases
+    pub fn switch(
+        &self,
+        ty: &CType,
+        scrutinee: CValue,
+        cases: Vec<SwitchCase>,
+        otherwise: Option<PhiBuilder>,
+    ) -> CResult<&Self> {
+        if cases.iter().any(|case| case.labels.is_empty()) {
+            return Err(CodegenError::EmptySwitchCase);
+        }
+        let (_, phi) = self.decl_tmp(ty)?;
+        let scrutinee = scrutinee.to_c(self.dialect, self)?;
+        let mut code = format!("switch({}) {{\n", scrutinee);
+        let arms = cases
+            .into_iter()
+            .map(|case| (Some(case.labels), case.fallthrough, case.block))
+            .chain(otherwise.map(|block| (None, false, block)))
+            .collect::<Vec<_>>();
+        let last = arms.len() - 1;
+        for (i, (labels, fallthrough, block)) in arms.into_iter().enumerate() {
+            let labels = match labels {
+                Some(labels) => labels
+                    .iter()
+                    .map(|label| Ok(format!("case {}:", label.to_c(self.dialect, self)?)))
+                    .collect::<CResult<Vec<_>>>()?
+                    .join("\n"),
+                None => "default:".to_string(),
+            };
+            let mut s = self.scope();
+            s.jumps.in_switch = true;
+            let s = block(s, phi.clone())?;
+            let block = s.current_source.lock().unwrap().clone();
+            // the marker keeps `-Wimplicit-fallthrough` quiet and has to
+            // precede the next label, the last arm just ends
+            let end = if !fallthrough {
+                end_arm(&s)
+            } else if i == last || s.ends_in_jump() {
+                "}\n"
+            } else {
+                match self.dialect {
+                    CDialect::Standard | CDialect::Parallel => {
+                        "}\n#if defined(__GNUC__)\n__attribute__((fallthrough));\n#endif\n"
+                    }
+                    CDialect::Kernel(KernelTarget::Cuda) => "}\n[[fallthrough]];\n",
+                    // no attribute syntax, GCC style comment only
+                    CDialect::Kernel(_) => "}\n/* fallthrough */\n",
+                }
+            };
+            code.push_str(&format!("{} {{\n{}{}", labels, block, end));
+        }
+        code.push_str("}\n");
         self.current_source.lock().unwrap().push_str(&code);
-        self
+        Ok(self)
+    }
+
+    /// leave the innermost loop or `switch`
+    pub fn break_loop(&self) -> CResult<&Self> {
+        if !self.jumps.in_loop && !self.jumps.in_switch {
+            return Err(CodegenError::MisplacedJump("break"));
+        }
+        self.push_statement("break;\n", Statement::Jump);
+        Ok(self)
+    }
+
+    /// start the next iteration of the innermost loop
+    pub fn continue_loop(&self) -> CResult<&Self> {
+        if !self.jumps.in_loop && !self.jumps.in_directive_loop {
+            return Err(CodegenError::MisplacedJump("continue"));
+        }
+        self.push_statement("continue;\n", Statement::Jump);
+        Ok(self)
+    }
+
+    /// labels of the enclosing function, `goto` cannot leave it
+    fn labels(&self, statement: &'static str) -> CResult<&Arc<Mutex<Labels>>> {
+        if self.dialect == CDialect::Kernel(KernelTarget::GLSL) {
+            return Err(CodegenError::UnsupportedFeature {
+                feature: "goto",
+                dialect: self.dialect,
+            });
+        }
+        self.jumps
+            .labels
+            .as_ref()
+            .ok_or(CodegenError::MisplacedJump(statement))
+    }
+
+    /// `name:`, a target for `goto` anywhere in the enclosing function
+    pub fn label(&self, name: Variable) -> CResult<&Self> {
+        let mut labels = self.labels("label")?.lock().unwrap();
+        if labels.defined.contains_key(&name) {
+            return Err(CodegenError::DuplicateLabel(name));
+        }
+        let scopes = self
+            .scopes
+            .iter()
+            .map(|scope| (scope.clone(), scope.lock().unwrap().len()))
+            .collect();
+        labels.defined.insert(name.clone(), scopes);
+        drop(labels);
+        // a label needs a statement after it before C23
+        let name = self.c_name(&name);
+        self.current_source
+            .lock()
+            .unwrap()
+            .push_str(&format!("{}:;\n", name));
+        Ok(self)
+    }
+
+    /// `goto name;`, the label may be defined later in the function
+    pub fn goto(&self, name: Variable) -> CResult<&Self> {
+        let mut labels = self.labels("goto")?.lock().unwrap();
+        self.check_backward_goto(&labels, &name)?;
+        labels.targets.insert(name.clone());
+        drop(labels);
+        let name = self.c_name(&name);
+        self.push_statement(&format!("goto {};\n", name), Statement::Jump);
+        Ok(self)
+    }
+
+    /// jump to `targets[state]`, `state` being an integer variable, an
+    /// out of range `state` continues with the next statement
+    ///
+    /// GNU C compilers dispatch with `goto *` through a table of label
+    /// addresses, other compilers and device code with a `switch`
+    pub fn goto_computed(&self, state: Variable, targets: Vec<Variable>) -> CResult<&Self> {
+        let labels = self.labels("goto")?;
+        let state = self.known_variable(&state)?;
+        let names = targets
+            .into_iter()
+            .map(|name| {
+                let mut labels = labels.lock().unwrap();
+                self.check_backward_goto(&labels, &name)?;
+                let c_name = self.c_name(&name);
+                labels.targets.insert(name);
+                Ok(c_name)
+            })
+            .collect::<CResult<Vec<_>>>()?;
+        let cases: String = names
+            .iter()
+            .enumerate()
+            .map(|(i, name)| format!("case {}: goto {};\n", i, name))
+            .collect();
+        let switch = format!("switch({}) {{\n{}}}\n", state, cases);
+        let code = if names.is_empty() {
+            String::new()
+        } else if matches!(self.dialect, CDialect::Standard | CDialect::Parallel) {
+            let addresses = names
+                .iter()
+                .map(|name| format!("&&{}", name))
+                .collect::<Vec<_>>()
+                .join(", ");
+            // the cast also sends negative states past the table
+            format!(
+                "#if defined(__GNUC__)\n\
+                 if((unsigned long long){state} < {}) {{\n\
+                 static void *const targets[] = {{{}}};\n\
+                 goto *targets[{state}];\n\
+                 }}\n\
+                 #else\n\
+                 {}\
+                 #endif\n",
+                names.len(),
+                addresses,
+                switch,
+                state = state
+            )
+        } else {
+            switch
+        };
+        self.current_source.lock().unwrap().push_str(&code);
+        Ok(self)
+    }
+
+    /// `return value;`, the value has to be given unless the enclosing
+    /// function returns `void`
+    pub fn ret(&self, value: Option<CValue>) -> CResult<&Self> {
+        let Some(ty) = &self.jumps.function else {
+            return Err(CodegenError::MisplacedJump("return"));
+        };
+        let code = match value {
+            Some(value) if *ty != CType::Void => {
+                format!("return {};\n", value.to_c(self.dialect, self)?)
+            }
+            None if *ty == CType::Void => "return;\n".to_string(),
+            _ => return Err(CodegenError::ReturnMismatch(ty.clone())),
+        };
+        self.push_statement(&code, Statement::Jump);
+        Ok(self)
     }
 
+    /// define an exported function, it gets an `extern` prototype in the header
     pub fn def(
         &self,
         name: Variable,
         ret: CType,
         args: Vec<(CType, Variable)>,
-        body: impl Fn(Self) -> Self,
-    ) -> &Self {
-        let name = string_to_escape_to_c_ansi_id(&self.module, &name);
-        let ret = ret.to_c(self.dialect, self).unwrap();
-        let args = args
+        body: impl Fn(Self) -> CResult<Self>,
+    ) -> CResult<&Self> {
+        self.def_with_visibility(Visibility::Exported, name, ret, args, body)
+    }
+
+    /// define a `static` function, private to the implementation file
+    pub fn def_static(
+        &self,
+        name: Variable,
+        ret: CType,
+        args: Vec<(CType, Variable)>,
+        body: impl Fn(Self) -> CResult<Self>,
+    ) -> CResult<&Self> {
+        self.def_with_visibility(Visibility::Static, name, ret, args, body)
+    }
+
+    pub fn def_with_visibility(
+        &self,
+        visibility: Visibility,
+        name: Variable,
+        ret: CType,
+        args: Vec<(CType, Variable)>,
+        body: impl Fn(Self) -> CResult<Self>,
+    ) -> CResult<&Self> {
+        if visibility == Visibility::Static && self.dialect == CDialect::Kernel(KernelTarget::GLSL)
+        {
+            return Err(CodegenError::UnsupportedFeature {
+                feature: "static functions",
+                dialect: self.dialect,
+            });
+        }
+        let name = self.c_name(&name);
+        let (prototype, body) = self.function(&name, ret, args, body)?;
+        self.c_file.lock().unwrap().functions.push(CFunction {
+            prototype,
+            body,
+            visibility,
+            entry_point: false,
+        });
+        Ok(self)
+    }
+
+    /// prototype and body of the function `c_name`, the arguments are
+    /// visible as variables inside `body`
+    pub(crate) fn function(
+        &self,
+        c_name: &str,
+        ret: CType,
+        args: Vec<(CType, Variable)>,
+        body: impl Fn(Self) -> CResult<Self>,
+    ) -> CResult<(String, String)> {
+        let mut variables = self.variables.lock().unwrap().clone();
+        let params = args
             .iter()
             .map(|(ty, name)| {
-                format!(
-                    "{} {}",
-                    ty.to_c(self.dialect, self).unwrap(),
-                    string_to_escape_to_c_ansi_id(&self.module, name)
+                variables.insert(name.clone(), ty.clone());
+                ty.declare(&self.c_name(name), self.dialect, self)
+            })
+            .collect::<CResult<Vec<_>>>()?;
+        let params = if !params.is_empty() {
+            params.join(", ")
+        } else if self.dialect == CDialect::Kernel(KernelTarget::GLSL) {
+            "".to_string()
+        } else {
+            "void".to_string()
+        };
+        // the return type wraps the function declarator, so a function
+        // returning a function pointer comes out as `T (*name(params))(...)`
+        let prototype = ret.declare(&format!("{}({})", c_name, params), self.dialect, self)?;
+        let mut s = self.scope_with(variables);
+        s.device = false;
+        let labels = Arc::new(Mutex::new(Labels::default()));
+        s.jumps = JumpTargets {
+            function: Some(ret),
+            labels: Some(labels.clone()),
+            ..Default::default()
+        };
+        let body = body(s)?;
+        labels.lock().unwrap().check()?;
+        let body = body.current_source.lock().unwrap().clone();
+        Ok((prototype, body))
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::c_cg::c_value::{CLiteral, FloatSuffix, IntegerSuffix, Radix};
+
+    #[test]
+    fn test_render_def() {
+        let context = Context::standard("m".to_string());
+        context.include("stdio.h");
+        context
+            .def("one".to_string(), CType::I32, vec![], |c| {
+                c.set(
+                    CType::I32,
+                    "x".to_string(),
+                    CValue::Literal(CLiteral::Int(1, IntegerSuffix::None, Radix::Decimal)),
+                )?;
+                Ok(c)
+            })
+            .unwrap();
+        context
+            .def(
+                "id".to_string(),
+                CType::I32,
+                vec![(CType::I32, "x".to_string())],
+                |c| {
+                    c.set(
+                        CType::I32,
+                        "x".to_string(),
+                        CValue::Variable("x".to_string()),
+                    )?;
+                    Ok(c)
+                },
+            )
+            .unwrap();
+        assert_eq!(
+            context.render(),
+            "#include <stdint.h>\n\
+             #include <stdio.h>\n\
+             \n\
+             int32_t m_MM_one(void);\n\
+             int32_t m_MM_id(int32_t m_MM_x);\n\
+             \n\
+             int32_t m_MM_one(void) {\n\
+             int32_t m_MM_x = 1;\n\
+             }\n\
+             \n\
+             int32_t m_MM_id(int32_t m_MM_x) {\n\
+             m_MM_x = m_MM_x;\n\
+             }\n"
+        );
+    }
+
+    #[test]
+    fn test_errors() {
+        let context = Context::standard("m".to_string());
+        assert_eq!(
+            context
+                .cond(&CType::I32, vec![], vec![], |c, _| Ok(c))
+                .err(),
+            Some(CodegenError::MalformedCond {
+                conditions: 0,
+                blocks: 0
+            })
+        );
+        assert_eq!(
+            context
+                .set(
+                    CType::I32,
+                    "x".to_string(),
+                    CValue::Array(CType::I32, vec![])
+                )
+                .err(),
+            Some(CodegenError::NotAnArray(CType::I32))
+        );
+        // a failed declaration leaves the name undeclared
+        context
+            .set(
+                CType::I32,
+                "x".to_string(),
+                CValue::Literal(CLiteral::Int(1, IntegerSuffix::None, Radix::Decimal)),
+            )
+            .unwrap();
+        assert_eq!(
+            context.current_source.lock().unwrap().as_str(),
+            "int32_t m_MM_x = 1;\n"
+        );
+        assert_eq!(
+            context
+                .set(
+                    CType::I32,
+                    "y".to_string(),
+                    CValue::AutoDiff("f".to_string(), vec![])
                 )
+                .err(),
+            Some(CodegenError::UnsupportedFeature {
+                feature: "automatic differentiation",
+                dialect: CDialect::Standard
             })
-            .collect::<Vec<_>>()
-            .join(", ");
-        let body = body(Context {
-            c_file: self.c_file.clone(),
-            dialect: self.dialect,
-            module: self.module.clone(),
-            variables: Mutex::new(self.variables.lock().unwrap().clone()),
-            current_source: Mutex::new(String::new()),
+        );
+    }
+
+    #[test]
+    fn test_match_tagged() {
+        let context = Context::standard("m".to_string());
+        let shape = CType::TaggedUnion {
+            name: "shape".to_string(),
+            variants: vec![
+                ("circle".to_string(), CType::F32),
+                ("empty".to_string(), CType::Void),
+            ],
+        };
+        context
+            .def(
+                "area".to_string(),
+                CType::F32,
+                vec![(shape.clone(), "s".to_string())],
+                |c| {
+                    c.match_tagged(
+                        &CType::F32,
+                        &shape,
+                        CValue::Variable("s".to_string()),
+                        vec![
+                            (
+                                "circle".to_string(),
+                                Box::new(|c, phi, r| {
+                                    c.set(CType::F32, phi, CValue::Variable(r.unwrap()))?;
+                                    Ok(c)
+                                }),
+                            ),
+                            (
+                                "empty".to_string(),
+                                Box::new(|c, phi, payload| {
+                                    assert_eq!(payload, None);
+                                    c.set(
+                                        CType::F32,
+                                        phi,
+                                        CValue::Literal(CLiteral::Float(0.5, FloatSuffix::F32)),
+                                    )?;
+                                    Ok(c)
+                                }),
+                            ),
+                        ],
+                        None,
+                    )?;
+                    Ok(c)
+                },
+            )
+            .unwrap();
+        let source = context.render();
+        assert!(source.contains(
+            "enum m_MM_shape_X3A__X3A_tag {\n\
+             m_MM_shape_X3A__X3A_tag_X2E_circle,\n\
+             m_MM_shape_X3A__X3A_tag_X2E_empty\n\
+             };\n\
+             struct m_MM_shape {\n\
+             enum m_MM_shape_X3A__X3A_tag tag;\n\
+             union {\n\
+             float circle;\n\
+             } payload;\n\
+             };\n"
+        ));
+        assert!(source.contains(
+            "static inline struct m_MM_shape m_MM_shape_X3A__X3A_circle(float value) {\n\
+             struct m_MM_shape result;\n\
+             result.tag = m_MM_shape_X3A__X3A_tag_X2E_circle;\n\
+             result.payload.circle = value;\n\
+             return result;\n\
+             }\n"
+        ));
+        // the constructors are private to the implementation file
+        let (header, _) = context.render_split("shape.h");
+        assert!(!header.contains("m_MM_shape_X3A__X3A_circle("));
+        assert!(source.contains("case m_MM_shape_X3A__X3A_tag_X2E_empty: {\n"));
+
+        let result = context.match_tagged(
+            &CType::F32,
+            &shape,
+            CValue::Variable("s".to_string()),
+            vec![],
+            None,
+        );
+        assert_eq!(
+            context
+                .match_tagged(
+                    &CType::F32,
+                    &shape,
+                    CValue::Variable("s".to_string()),
+                    vec![
+                        ("empty".to_string(), Box::new(|c, _, _| Ok(c))),
+                        ("empty".to_string(), Box::new(|c, _, _| Ok(c))),
+                    ],
+                    Some(Box::new(|c, _| Ok(c))),
+                )
+                .err(),
+            Some(CodegenError::DuplicateArm {
+                ty: "shape".to_string(),
+                variant: "empty".to_string()
+            })
+        );
+        assert_eq!(
+            result.err(),
+            Some(CodegenError::NonExhaustiveMatch {
+                ty: "shape".to_string(),
+                missing: vec!["circle".to_string(), "empty".to_string()]
+            })
+        );
+        let never = CType::TaggedUnion {
+            name: "never".to_string(),
+            variants: vec![],
+        };
+        assert_eq!(
+            never.to_c(CDialect::Standard, &context),
+            Err(CodegenError::NoVariants("never".to_string()))
+        );
+        assert!(
+            !context
+                .c_file
+                .lock()
+                .unwrap()
+                .type_names
+                .iter()
+                .any(|name| name.contains("never"))
+        );
+    }
+
+    #[test]
+    fn test_data_model() {
+        let context = Context::standard("m".to_string()).with_data_model(DataModel::LLP64);
+        context
+            .def(
+                "f".to_string(),
+                CType::U64,
+                vec![(CType::I32, "x".to_string())],
+                Ok,
+            )
+            .unwrap();
+        assert_eq!(
+            context.render(),
+            "unsigned long long int m_MM_f(signed int m_MM_x);\n\
+             \n\
+             unsigned long long int m_MM_f(signed int m_MM_x) {\n\
+             }\n"
+        );
+    }
+
+    fn int(v: i128) -> CValue {
+        CValue::Literal(CLiteral::Int(v, IntegerSuffix::None, Radix::Decimal))
+    }
+
+    #[test]
+    fn test_loops_and_switch() {
+        use crate::c_cg::c_value::BinaryOp;
+        let x = || CValue::Variable("x".to_string());
+        let context = Context::standard("m".to_string());
+        context
+            .def(
+                "f".to_string(),
+                CType::I32,
+                vec![(CType::I32, "x".to_string())],
+                |c| {
+                    c.while_loop(
+                        &CType::Void,
+                        CValue::BinOp(BinaryOp::Gt, Box::new(x()), Box::new(int(10))),
+                        |c, _| {
+                            c.set(
+                                CType::I32,
+                                "x".to_string(),
+                                CValue::BinOp(BinaryOp::Div, Box::new(x()), Box::new(int(2))),
+                            )?;
+                            c.continue_loop()?;
+                            Ok(c)
+                        },
+                    )?;
+                    c.do_while(&CType::Void, int(0), |c, _| {
+                        c.break_loop()?;
+                        Ok(c)
+                    })?;
+                    c.switch(
+                        &CType::Void,
+                        x(),
+                        vec![
+                            SwitchCase {
+                                labels: vec![int(1), int(2)],
+                                fallthrough: true,
+                                block: Box::new(|c, _| {
+                                    c.set(CType::I32, "x".to_string(), int(0))?;
+                                    Ok(c)
+                                }),
+                            },
+                            SwitchCase {
+                                labels: vec![int(3)],
+                                fallthrough: false,
+                                block: Box::new(|c, _| {
+                                    c.ret(Some(int(3)))?;
+                                    Ok(c)
+                                }),
+                            },
+                        ],
+                        Some(Box::new(|c, _| Ok(c))),
+                    )?;
+                    c.ret(Some(x()))?;
+                    Ok(c)
+                },
+            )
+            .unwrap();
+        assert_eq!(
+            context.render(),
+            "#include <stdint.h>\n\
+             \n\
+             int32_t m_MM_f(int32_t m_MM_x);\n\
+             \n\
+             int32_t m_MM_f(int32_t m_MM_x) {\n\
+             while(m_MM_x > 10) {\n\
+             m_MM_x = m_MM_x / 2;\n\
+             continue;\n\
+             }\n\
+             do {\n\
+             break;\n\
+             } while(0);\n\
+             switch(m_MM_x) {\n\
+             case 1:\n\
+             case 2: {\n\
+             m_MM_x = 0;\n\
+             }\n\
+             #if defined(__GNUC__)\n\
+             __attribute__((fallthrough));\n\
+             #endif\n\
+             case 3: {\n\
+             return 3;\n\
+             }\n\
+             default: {\n\
+             break;\n\
+             }\n\
+             }\n\
+             return m_MM_x;\n\
+             }\n"
+        );
+
+        // jumps need something to jump out of
+        assert_eq!(
+            context.break_loop().err(),
+            Some(CodegenError::MisplacedJump("break"))
+        );
+        assert_eq!(
+            context.ret(None).err(),
+            Some(CodegenError::MisplacedJump("return"))
+        );
+        let result = context.def("g".to_string(), CType::Void, vec![], |c| {
+            c.switch(
+                &CType::Void,
+                int(0),
+                vec![],
+                Some(Box::new(|c, _| {
+                    c.continue_loop()?;
+                    Ok(c)
+                })),
+            )?;
+            Ok(c)
         });
-        let body = body.current_source.lock().unwrap().clone();
-        let code = format!("{} {}({}) {{{}}}", ret, name, args, body);
-        self.global_inline_c(code);
-        self
+        assert_eq!(result.err(), Some(CodegenError::MisplacedJump("continue")));
+        let result = context.def("h".to_string(), CType::Void, vec![], |c| {
+            c.ret(Some(int(1)))?;
+            Ok(c)
+        });
+        assert_eq!(
+            result.err(),
+            Some(CodegenError::ReturnMismatch(CType::Void))
+        );
+
+        // CUDA is C++, and the last arm has no label to fall into
+        let cuda = Context::cuda("m".to_string());
+        let arm = |label| SwitchCase {
+            labels: vec![int(label)],
+            fallthrough: true,
+            block: Box::new(|c, _| Ok(c)),
+        };
+        cuda.switch(&CType::Void, int(0), vec![arm(0), arm(1)], None)
+            .unwrap();
+        assert_eq!(
+            *cuda.current_source.lock().unwrap(),
+            "switch(0) {\n\
+             case 0: {\n\
+             }\n\
+             [[fallthrough]];\n\
+             case 1: {\n\
+             }\n\
+             }\n"
+        );
+
+        // a `break` in inline C leaves its `do while`, not the arm
+        let context = Context::standard("m".to_string());
+        let case = SwitchCase {
+            labels: vec![int(0)],
+            fallthrough: false,
+            block: Box::new(|c, _| Ok(c.local_inline_c("f();\nbreak;".to_string()))),
+        };
+        context
+            .switch(&CType::Void, int(0), vec![case], None)
+            .unwrap();
+        assert_eq!(
+            *context.current_source.lock().unwrap(),
+            "switch(0) {\n\
+             case 0: {\n\
+             do {f();\n\
+             break;} while(0);\n\
+             break;\n\
+             }\n\
+             }\n"
+        );
+    }
+
+    #[test]
+    fn test_goto() {
+        let context = Context::standard("m".to_string());
+        context
+            .def(
+                "resume".to_string(),
+                CType::I32,
+                vec![(CType::I32, "state".to_string())],
+                |c| {
+                    c.goto_computed(
+                        "state".to_string(),
+                        vec!["start".to_string(), "done".to_string()],
+                    )?;
+                    c.label("start".to_string())?;
+                    c.goto("done".to_string())?;
+                    c.label("done".to_string())?;
+                    c.ret(Some(int(1)))?;
+                    Ok(c)
+                },
+            )
+            .unwrap();
+        assert_eq!(
+            context.render(),
+            "#include <stdint.h>\n\
+             \n\
+             int32_t m_MM_resume(int32_t m_MM_state);\n\
+             \n\
+             int32_t m_MM_resume(int32_t m_MM_state) {\n\
+             #if defined(__GNUC__)\n\
+             if((unsigned long long)m_MM_state < 2) {\n\
+             static void *const targets[] = {&&m_MM_start, &&m_MM_done};\n\
+             goto *targets[m_MM_state];\n\
+             }\n\
+             #else\n\
+             switch(m_MM_state) {\n\
+             case 0: goto m_MM_start;\n\
+             case 1: goto m_MM_done;\n\
+             }\n\
+             #endif\n\
+             m_MM_start:;\n\
+             goto m_MM_done;\n\
+             m_MM_done:;\n\
+             return 1;\n\
+             }\n"
+        );
+
+        let result = context.def("f".to_string(), CType::Void, vec![], |c| {
+            c.goto("missing".to_string())?;
+            Ok(c)
+        });
+        assert_eq!(
+            result.err(),
+            Some(CodegenError::UndefinedLabel("missing".to_string()))
+        );
+        let result = context.def("g".to_string(), CType::Void, vec![], |c| {
+            c.label("l".to_string())?;
+            c.block(&CType::Void, |c, _| {
+                c.label("l".to_string())?;
+                Ok(c)
+            })?;
+            Ok(c)
+        });
+        assert_eq!(
+            result.err(),
+            Some(CodegenError::DuplicateLabel("l".to_string()))
+        );
+        assert_eq!(
+            context.goto("l".to_string()).err(),
+            Some(CodegenError::MisplacedJump("goto"))
+        );
+        // C++ cannot jump over an initialization
+        let cuda = Context::cuda("m".to_string());
+        let result = cuda.def("k".to_string(), CType::Void, vec![], |c| {
+            c.goto("end".to_string())?;
+            c.set(CType::I32, "x".to_string(), int(0))?;
+            c.label("end".to_string())?;
+            Ok(c)
+        });
+        assert_eq!(
+            result.err(),
+            Some(CodegenError::JumpOverDeclaration("x".to_string()))
+        );
+        let forward = |declare: fn(&Context) -> CResult<&Context>| {
+            cuda.def("k".to_string(), CType::Void, vec![], |c| {
+                c.goto("end".to_string())?;
+                declare(&c)?;
+                c.label("end".to_string())?;
+                Ok(c)
+            })
+            .err()
+        };
+        // the `phi` of a block is a declaration too
+        assert!(matches!(
+            forward(|c| c.block(&CType::I32, |c, _| Ok(c))),
+            Some(CodegenError::JumpOverDeclaration(_))
+        ));
+        assert_eq!(
+            forward(|c| c.for_loop(
+                Some((CType::I32, "i".to_string(), int(0))),
+                None,
+                None,
+                |c| Ok((c, "i".to_string()))
+            )),
+            Some(CodegenError::JumpOverDeclaration("i".to_string()))
+        );
+        // jumping back into a scope past a declaration
+        let result = cuda.def("k".to_string(), CType::Void, vec![], |c| {
+            c.block(&CType::Void, |c, _| {
+                c.set(CType::I32, "x".to_string(), int(0))?;
+                c.label("back".to_string())?;
+                Ok(c)
+            })?;
+            c.goto("back".to_string())?;
+            Ok(c)
+        });
+        assert_eq!(
+            result.err(),
+            Some(CodegenError::JumpOverDeclaration("x".to_string()))
+        );
+        // but not from within it, nor out of a scope
+        cuda.def("k".to_string(), CType::Void, vec![], |c| {
+            c.label("top".to_string())?;
+            c.block(&CType::Void, |c, _| {
+                c.set(CType::I32, "x".to_string(), int(0))?;
+                c.label("back".to_string())?;
+                c.set(CType::I32, "y".to_string(), int(1))?;
+                c.goto("back".to_string())?;
+                c.goto("top".to_string())?;
+                Ok(c)
+            })?;
+            Ok(c)
+        })
+        .unwrap();
+        // labels of different functions do not clash
+        context
+            .def("h".to_string(), CType::Void, vec![], |c| {
+                c.label("start".to_string())?;
+                Ok(c)
+            })
+            .unwrap();
     }
 }
@@ -1,10 +1,17 @@
-use std::collections::BTreeMap;
+use crate::escape::string_to_escape_to_c_ansi_id;
 
-use crate::escape::get_temp_variable_name;
+use super::{
+    CDialect, KernelTarget, ToC,
+    c_cuda::cuda_type,
+    c_error::{CResult, CodegenError},
+    c_file::{CFunction, Visibility},
+    c_glsl::glsl_type,
+    c_opencl::opencl_type,
+    c_stmt::Context,
+    c_value::{CLiteral, IntegerSuffix, Radix},
+};
 
-use super::{CDialect, ToC, c_stmt::Context};
-
-#[derive(Debug, Clone, PartialEq, Eq)]
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
 pub enum Repr {
     Packed,
     Aligned(usize),
@@ -12,20 +19,21 @@ pub enum Repr {
 }
 
 impl ToC for Repr {
-    fn to_c(&self, _dialect: CDialect, _c_file: &Context) -> Option<String> {
+    fn to_c(&self, _dialect: CDialect, _c_file: &Context) -> CResult<String> {
         match self {
-            Repr::Packed => Some("__attribute__ ((packed))".to_string()),
-            Repr::Aligned(align) => Some(format!("__attribute__ ((aligned({})))", align)),
+            Repr::Packed => Ok("__attribute__ ((packed))".to_string()),
+            Repr::Aligned(align) => Ok(format!("__attribute__ ((aligned({})))", align)),
             Repr::PackedAligned(align) => {
-                Some(format!("__attribute__ ((packed, aligned({})))", align))
+                Ok(format!("__attribute__ ((packed, aligned({})))", align))
             }
         }
     }
 }
 
-#[derive(Debug, Clone, PartialEq, Eq)]
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
 pub enum CType {
     Void,
+    Bool,
     I8,
     I16,
     I32,
@@ -37,13 +45,62 @@ pub enum CType {
     F32,
     F64,
 
+    /// fields are emitted in declaration order
+    ///
+    /// a named struct is nominal, an anonymous one is interned so that
+    /// structurally identical structs share a single definition
     Struct {
+        name: Option<String>,
         repr: Option<Repr>,
-        fields: BTreeMap<String, CType>,
+        fields: Vec<(String, CType)>,
+    },
+
+    /// `struct name` of a named struct without requiring its definition,
+    /// e.g. for a pointer to the struct inside its own fields
+    StructRef(String),
+
+    /// `union name { ... }`, named or interned like `Struct`
+    Union {
+        name: Option<String>,
+        fields: Vec<(String, CType)>,
+    },
+
+    /// `enum name { ... }`, variants without a discriminant continue
+    /// numbering from the previous one like in C
+    ///
+    /// `underlying` fixes the representation with the C23 `enum name : ty`
+    /// syntax and must be an integer type
+    Enum {
+        name: String,
+        underlying: Option<Box<CType>>,
+        variants: Vec<(String, Option<i64>)>,
+    },
+
+    /// a sum type lowered to a tag enum and a struct holding the tag and a
+    /// union of the payloads, see `Context::match_tagged` for matching
+    ///
+    /// for every variant a constructor `static inline` function is generated,
+    /// reachable as `CValue::Variable("name::variant")`; a `Void` variant
+    /// carries no payload and its constructor takes no argument
+    TaggedUnion {
+        name: String,
+        variants: Vec<(String, CType)>,
+    },
+
+    /// `typedef ty name;`
+    Typedef {
+        name: String,
+        ty: Box<CType>,
     },
 
+    /// `struct { ty name : width; ... }`, interned like `Struct`
+    ///
+    /// the base type must be an integer type, a field with an empty name is
+    /// unnamed padding and is the only kind of field allowed a zero width
     BitField {
-        fields: BTreeMap<String, (CType, usize)>,
+        name: Option<String>,
+        repr: Option<Repr>,
+        fields: Vec<(String, (CType, usize))>,
     },
 
     Array {
@@ -71,12 +128,12 @@ pub enum CType {
     // ========== glsl types ==========
     GLSLExtension(GLSLType),
     // ========== opencl types ==========
-    OpenCLExtension,
+    OpenCLExtension(OpenCLType),
     // ========== cuda types ==========
-    CudaExtension,
+    CudaExtension(CudaType),
 }
 
-#[derive(Debug, Clone, PartialEq, Eq)]
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
 pub enum ModernCTypes {
     // extended types
     F16,
@@ -85,7 +142,7 @@ pub enum ModernCTypes {
     F128,
 }
 
-#[derive(Debug, Clone, PartialEq, Eq)]
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
 pub enum GLSLType {
     Vec {
         // i b u d support
@@ -101,72 +158,1026 @@ pub enum GLSLType {
         // 2, 3, 4
         cols: usize,
     },
-    // samplers
-    // images
-    // atomic counters
+    /// `sampler2D`, `isampler3D`, ..., `ty` is `F32`, `I32` or `U32`
+    Sampler {
+        ty: Box<CType>,
+        dim: TextureDim,
+    },
+    /// `image2D`, ..., declared with its format qualifier
+    Image {
+        ty: Box<CType>,
+        dim: TextureDim,
+        format: ImageFormat,
+    },
+    AtomicUint,
+    /// the instance of the interface block `name`, the type of the variable
+    /// a block declares; it has no spelling of its own
+    Block {
+        name: String,
+        fields: Vec<(String, CType)>,
+    },
 }
 
-impl ToC for CType {
-    fn to_c(&self, dialect: CDialect, c_file: &Context) -> Option<String> {
-        match self {
-            CType::I8 => Some("signed char".to_string()),
-            CType::I16 => Some("signed short int".to_string()),
-            CType::I32 => Some("signed int".to_string()),
-            CType::I64 => Some("signed long int".to_string()),
-
-            CType::U8 => Some("unsigned char".to_string()),
-            CType::U16 => Some("unsigned short int".to_string()),
-            CType::U32 => Some("unsigned int".to_string()),
-            CType::U64 => Some("unsigned long int".to_string()),
-            CType::F32 => Some("float".to_string()),
-            CType::F64 => Some("double".to_string()),
-
-            CType::Struct { repr, fields } => {
-                let inner = fields
-                    .iter()
-                    .map(|(name, ty)| format!("{} {};", ty.to_c(dialect, c_file).unwrap(), name))
-                    .collect::<Vec<_>>()
-                    .join("\n");
-                let name = get_temp_variable_name(&c_file.module);
-                let repr = repr
-                    .clone()
-                    .map(|r| r.to_c(dialect, c_file).unwrap())
-                    .unwrap_or("".to_string());
-                let code = format!("struct {} {} {{\n{}\n}};", name, repr, inner);
-                c_file.global_inline_c(code);
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum TextureDim {
+    D1,
+    D2,
+    D3,
+    Cube,
+    D2Array,
+    Buffer,
+}
 
-                Some(format!("struct {}", name))
-            }
+/// format layout qualifiers of images, named as in GLSL
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum ImageFormat {
+    Rgba32f,
+    Rgba16f,
+    Rg32f,
+    R32f,
+    Rgba8,
+    Rgba32i,
+    R32i,
+    Rgba32ui,
+    R32ui,
+}
 
-            CType::Array { ty, size } => {
-                let size_str = size.map(|s| s.to_string()).unwrap_or("".to_string());
-                Some(format!(
-                    "{}[{}]",
-                    ty.to_c(dialect, c_file).unwrap(),
-                    size_str
-                ))
-            }
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub enum OpenCLType {
+    /// `float4`, `int2`, ..., `size` is 2, 3, 4, 8 or 16
+    Vector { ty: Box<CType>, size: usize },
+    /// `ty` in an address space, qualifies the pointee like `Const`:
+    /// a `Pointer` to `AddressSpace { Global, F32 }` is `__global float *`
+    AddressSpace { space: AddressSpace, ty: Box<CType> },
+}
 
-            CType::Pointer { ty } => Some(format!("{}*", ty.to_c(dialect, c_file).unwrap())),
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub enum CudaType {
+    /// grid and block dimensions of a launch
+    Dim3,
+    /// `float4`, `int2`, ..., `size` is 1 to 4
+    Vector { ty: Box<CType>, size: usize },
+}
 
-            CType::Const { ty } => Some(format!("{} const", ty.to_c(dialect, c_file).unwrap())),
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum AddressSpace {
+    Global,
+    Local,
+    Constant,
+    Private,
+}
+
+/// C rejects duplicate member names, catch them before emitting the struct
+pub(crate) fn check_unique_fields<'a>(names: impl Iterator<Item = &'a String>) -> CResult<()> {
+    let mut seen = std::collections::BTreeSet::new();
+    for name in names {
+        if !seen.insert(name) {
+            return Err(CodegenError::DuplicateField(name.clone()));
+        }
+    }
+    Ok(())
+}
+
+impl CType {
+    /// declare `name` with this type, e.g. `int (*name)[4]` for a pointer to
+    /// an array, an empty `name` gives the abstract declarator `int (*)[4]`
+    /// used in casts and parameter lists
+    pub fn declare(&self, name: &str, dialect: CDialect, context: &Context) -> CResult<String> {
+        Ok(self
+            .declarator(name.to_string(), dialect, context)?
+            .trim_end()
+            .to_string())
+    }
 
+    /// C declarators read inside out: wrap `inner` in the derivation of
+    /// this type and hand it to the type it is derived from
+    fn declarator(&self, inner: String, dialect: CDialect, context: &Context) -> CResult<String> {
+        // GLSL has no pointers, OpenCL C no function pointers
+        let unsupported = match dialect {
+            CDialect::Kernel(KernelTarget::GLSL) => self.is_pointer(),
+            CDialect::Kernel(KernelTarget::OpenCL) => {
+                matches!(self, CType::FunctionPointer { .. })
+            }
+            _ => false,
+        };
+        if unsupported {
+            return Err(CodegenError::UnsupportedType {
+                ty: self.clone(),
+                dialect,
+            });
+        }
+        match self {
+            CType::Pointer { ty } => ty.declarator(format!("*{}", inner), dialect, context),
+            CType::Array { ty, size } => {
+                // `*` binds looser than `[]`, a pointer to an array needs parentheses
+                let inner = if inner.starts_with('*') {
+                    format!("({})", inner)
+                } else {
+                    inner
+                };
+                let size = size.map(|s| s.to_string()).unwrap_or("".to_string());
+                ty.declarator(format!("{}[{}]", inner, size), dialect, context)
+            }
+            CType::Const { ty } => match ty.as_ref() {
+                // the pointer itself is const: `T *const name`
+                CType::Pointer { ty } => {
+                    ty.declarator(format!("*const {}", inner), dialect, context)
+                }
+                CType::FunctionPointer { .. } => {
+                    let inner = format!("const {}", inner);
+                    ty.declarator(inner, dialect, context)
+                }
+                // arrays cannot be qualified, their elements are
+                CType::Array { ty, size } => CType::Array {
+                    ty: Box::new(CType::Const { ty: ty.clone() }),
+                    size: *size,
+                }
+                .declarator(inner, dialect, context),
+                _ => Ok(format!("const {}", ty.declarator(inner, dialect, context)?)),
+            },
+            CType::OpenCLExtension(OpenCLType::AddressSpace { space, ty }) => Ok(format!(
+                "{} {}",
+                space.to_c(dialect, context)?,
+                ty.declarator(inner, dialect, context)?
+            )),
             CType::FunctionPointer {
                 return_ty,
                 arguments,
             } => {
-                let name = get_temp_variable_name(&c_file.module);
-                let return_ty = return_ty.to_c(dialect, c_file).unwrap();
                 let arguments = arguments
                     .iter()
-                    .map(|arg| arg.to_c(dialect, c_file).unwrap())
-                    .collect::<Vec<_>>()
-                    .join(", ");
-                let code = format!("typedef {} (*{})({});", return_ty, name, arguments);
-                c_file.global_inline_c(code);
-                Some(name)
+                    .map(|arg| arg.declare("", dialect, context))
+                    .collect::<CResult<Vec<_>>>()?;
+                let arguments = if arguments.is_empty() {
+                    "void".to_string()
+                } else {
+                    arguments.join(", ")
+                };
+                return_ty.declarator(format!("(*{})({})", inner, arguments), dialect, context)
+            }
+            _ => {
+                let base = self.to_c(dialect, context)?;
+                if inner.is_empty() {
+                    Ok(base)
+                } else {
+                    Ok(format!("{} {}", base, inner))
+                }
+            }
+        }
+    }
+
+    /// pointers and function pointers, possibly const qualified
+    pub fn is_pointer(&self) -> bool {
+        match self {
+            CType::Pointer { .. } | CType::FunctionPointer { .. } => true,
+            CType::Const { ty } => ty.is_pointer(),
+            _ => false,
+        }
+    }
+
+    /// inclusive range of values of an integer type, `None` for any other type
+    pub fn integer_range(&self) -> Option<(i128, i128)> {
+        let bits = self.integer_bits()?;
+        match self {
+            CType::I8 | CType::I16 | CType::I32 | CType::I64 => {
+                Some((-(1i128 << (bits - 1)), (1i128 << (bits - 1)) - 1))
             }
+            _ => Some((0, (1i128 << bits) - 1)),
+        }
+    }
+
+    /// width in bits of an integer type, `None` for any other type
+    pub fn integer_bits(&self) -> Option<usize> {
+        match self {
+            CType::I8 | CType::U8 => Some(8),
+            CType::I16 | CType::U16 => Some(16),
+            CType::I32 | CType::U32 => Some(32),
+            CType::I64 | CType::U64 => Some(64),
             _ => None,
         }
     }
 }
+
+fn check_bit_field(field: &str, ty: &CType, width: usize) -> CResult<()> {
+    let Some(bits) = ty.integer_bits() else {
+        return Err(CodegenError::InvalidBitFieldType {
+            field: field.to_string(),
+            ty: ty.clone(),
+        });
+    };
+    if width > bits || (width == 0 && !field.is_empty()) {
+        return Err(CodegenError::InvalidBitFieldWidth {
+            field: field.to_string(),
+            width,
+            max: bits,
+        });
+    }
+    Ok(())
+}
+
+pub(crate) fn members_to_c(
+    fields: &[(String, CType)],
+    dialect: CDialect,
+    context: &Context,
+) -> CResult<String> {
+    Ok(fields
+        .iter()
+        .map(|(name, ty)| Ok(format!("{};", ty.declare(name, dialect, context)?)))
+        .collect::<CResult<Vec<_>>>()?
+        .join("\n"))
+}
+
+pub(crate) fn check_union_dialect(dialect: CDialect) -> CResult<()> {
+    if !matches!(
+        dialect,
+        CDialect::Standard
+            | CDialect::Parallel
+            | CDialect::Kernel(KernelTarget::OpenCL)
+            | CDialect::Kernel(KernelTarget::Cuda)
+    ) {
+        return Err(CodegenError::UnsupportedFeature {
+            feature: "union",
+            dialect,
+        });
+    }
+    Ok(())
+}
+
+/// C name of `variant` of the enum `name`, see `CValue::Enumerator`
+///
+/// spelled `name.variant`, distinct from the `name::variant` constructors
+/// of a tagged union of the same name
+pub fn enumerator(module: &str, name: &str, variant: &str) -> String {
+    string_to_escape_to_c_ansi_id(module, &format!("{}.{}", name, variant))
+}
+
+/// C name of the enumerator tagging `variant` of the tagged union `name`
+pub fn tag_enumerator(module: &str, name: &str, variant: &str) -> String {
+    enumerator(module, &format!("{}::tag", name), variant)
+}
+
+fn define_enum(
+    name: &str,
+    c_name: &str,
+    underlying: Option<&CType>,
+    variants: &[(String, Option<i64>)],
+    dialect: CDialect,
+    context: &Context,
+) -> CResult<()> {
+    if variants.is_empty() {
+        return Err(CodegenError::NoVariants(name.to_string()));
+    }
+    // without a fixed type the enumerators have to be `int` constants
+    let range_ty = underlying.unwrap_or(&CType::I32);
+    let Some((min, max)) = range_ty.integer_range() else {
+        return Err(CodegenError::InvalidEnumType(range_ty.clone()));
+    };
+    let mut next = 0i128;
+    for (variant, value) in variants {
+        let value = value.map(i128::from).unwrap_or(next);
+        if value < min || value > max {
+            return Err(CodegenError::EnumeratorOutOfRange {
+                variant: variant.clone(),
+                value,
+                ty: range_ty.clone(),
+            });
+        }
+        next = value + 1;
+    }
+    let underlying = match underlying {
+        Some(ty) => format!(" : {}", ty.to_c(dialect, context)?),
+        None => "".to_string(),
+    };
+    let enumerators = variants
+        .iter()
+        .map(|(variant, value)| {
+            let variant = enumerator(&context.module, name, variant);
+            Ok(match value {
+                // the minimum of the type is spelled `-MAX - 1`
+                Some(value) => format!(
+                    "{} = {}",
+                    variant,
+                    CLiteral::Int(i128::from(*value), IntegerSuffix::None, Radix::Decimal)
+                        .to_c(dialect, context)?
+                ),
+                None => variant,
+            })
+        })
+        .collect::<CResult<Vec<_>>>()?
+        .join(",\n");
+    context.type_definition(format!(
+        "enum {}{} {{\n{}\n}};",
+        c_name, underlying, enumerators
+    ));
+    Ok(())
+}
+
+fn define_tagged_union(
+    name: &str,
+    c_name: &str,
+    variants: &[(String, CType)],
+    dialect: CDialect,
+    context: &Context,
+) -> CResult<()> {
+    if variants.is_empty() {
+        return Err(CodegenError::NoVariants(name.to_string()));
+    }
+    let module = &context.module;
+    let tag = CType::Enum {
+        name: format!("{}::tag", name),
+        underlying: None,
+        variants: variants
+            .iter()
+            .map(|(variant, _)| (variant.clone(), None))
+            .collect(),
+    }
+    .to_c(dialect, context)?;
+
+    let payloads = variants
+        .iter()
+        .filter(|(_, ty)| *ty != CType::Void)
+        .cloned()
+        .collect::<Vec<_>>();
+    let payload = if payloads.is_empty() {
+        "".to_string()
+    } else {
+        format!(
+            "\nunion {{\n{}\n}} payload;",
+            members_to_c(&payloads, dialect, context)?
+        )
+    };
+    context.forward_declaration(format!("struct {};", c_name));
+    context.type_definition(format!(
+        "struct {} {{\n{} tag;{}\n}};",
+        c_name, tag, payload
+    ));
+
+    for (variant, ty) in variants {
+        let constructor = string_to_escape_to_c_ansi_id(module, &format!("{}::{}", name, variant));
+        let (param, init) = if *ty == CType::Void {
+            ("void".to_string(), "".to_string())
+        } else {
+            (
+                ty.declare("value", dialect, context)?,
+                format!("result.payload.{} = value;\n", variant),
+            )
+        };
+        // a helper of the implementation file like the other generated
+        // functions, so the header does not define it; `inline` keeps
+        // unused constructors from warning
+        context.c_file.lock().unwrap().functions.push(CFunction {
+            prototype: format!("inline struct {} {}({})", c_name, constructor, param),
+            body: format!(
+                "struct {} result;\nresult.tag = {};\n{}return result;\n",
+                c_name,
+                tag_enumerator(module, name, variant),
+                init
+            ),
+            visibility: Visibility::Static,
+            entry_point: false,
+        });
+    }
+    Ok(())
+}
+
+/// look `ty` up in the type registry of the file, on first use `define` is
+/// called with the C name of the type to emit its definition
+///
+/// `name` is the user supplied name of a nominal type, anonymous types get a
+/// generated one; `spelling` turns the C name into the type as written in C
+fn intern(
+    context: &Context,
+    ty: &CType,
+    name: Option<&str>,
+    spelling: impl Fn(&str) -> String,
+    define: impl FnOnce(&str) -> CResult<()>,
+) -> CResult<String> {
+    if let Some(spelled) = context.c_file.lock().unwrap().types.get(ty) {
+        return Ok(spelled.clone());
+    }
+
+    let c_name = match name {
+        Some(name) => {
+            let c_name = context.c_name(name);
+            if context.c_file.lock().unwrap().type_names.contains(&c_name) {
+                return Err(CodegenError::ConflictingType(name.to_string()));
+            }
+            c_name
+        }
+        None => context.temp_name(),
+    };
+    define(&c_name)?;
+
+    let spelled = spelling(&c_name);
+    let mut c_file = context.c_file.lock().unwrap();
+    c_file.type_names.insert(c_name);
+    c_file.types.insert(ty.clone(), spelled.clone());
+    Ok(spelled)
+}
+
+impl ToC for CType {
+    fn to_c(&self, dialect: CDialect, c_file: &Context) -> CResult<String> {
+        if dialect == CDialect::Kernel(KernelTarget::GLSL)
+            && let Some(ty) = glsl_type(self, dialect)?
+        {
+            return Ok(ty);
+        }
+        if dialect == CDialect::Kernel(KernelTarget::OpenCL)
+            && let Some(ty) = opencl_type(self, dialect, c_file)?
+        {
+            return Ok(ty);
+        }
+        if let CType::CudaExtension(ty) = self {
+            return cuda_type(ty, dialect);
+        }
+        match self {
+            CType::Void => Ok("void".to_string()),
+            CType::Bool => {
+                c_file.include("stdbool.h");
+                Ok("bool".to_string())
+            }
+            CType::I8
+            | CType::I16
+            | CType::I32
+            | CType::I64
+            | CType::U8
+            | CType::U16
+            | CType::U32
+            | CType::U64 => {
+                let bits = self.integer_bits().unwrap();
+                let signed = matches!(self, CType::I8 | CType::I16 | CType::I32 | CType::I64);
+                let mut c_file = c_file.c_file.lock().unwrap();
+                match c_file.data_model {
+                    Some(model) => Ok(model.integer(bits, signed).to_string()),
+                    None => {
+                        c_file.include("stdint.h");
+                        let sign = if signed { "" } else { "u" };
+                        Ok(format!("{}int{}_t", sign, bits))
+                    }
+                }
+            }
+            CType::F32 => Ok("float".to_string()),
+            CType::F64 => Ok("double".to_string()),
+
+            CType::ModernCExtension(ty) => {
+                let (guard, spelling) = match ty {
+                    ModernCTypes::F16 => (
+                        "#if !defined(__FLT16_MAX__)\n#error \"_Float16 is not supported by this compiler\"\n#endif",
+                        "_Float16",
+                    ),
+                    ModernCTypes::I128 => (
+                        "#if !defined(__SIZEOF_INT128__)\n#error \"__int128 is not supported by this compiler\"\n#endif",
+                        "__int128",
+                    ),
+                    ModernCTypes::U128 => (
+                        "#if !defined(__SIZEOF_INT128__)\n#error \"__int128 is not supported by this compiler\"\n#endif",
+                        "unsigned __int128",
+                    ),
+                    ModernCTypes::F128 => (
+                        "#if !defined(__FLT128_MAX__) && !defined(__SIZEOF_FLOAT128__)\n#error \"128-bit floats are not supported by this compiler\"\n#endif",
+                        "",
+                    ),
+                };
+                let module = &c_file.module;
+                let mut c_file = c_file.c_file.lock().unwrap();
+                c_file.preamble(guard);
+                if *ty != ModernCTypes::F128 {
+                    return Ok(spelling.to_string());
+                }
+                // gcc spells it `_Float128`, clang only knows `__float128`
+                let name = string_to_escape_to_c_ansi_id(module, "_Float128");
+                let typedef = format!(
+                    "#if defined(__FLT128_MAX__)\ntypedef _Float128 {};\n#else\ntypedef __float128 {};\n#endif",
+                    name, name
+                );
+                if !c_file.type_definitions.contains(&typedef) {
+                    c_file.type_definitions.push(typedef);
+                }
+                Ok(name)
+            }
+
+            CType::Struct { name, repr, fields } => {
+                check_unique_fields(fields.iter().map(|(name, _)| name))?;
+                // GLSL names struct types bare and cannot declare them ahead
+                let glsl = dialect == CDialect::Kernel(KernelTarget::GLSL);
+                intern(
+                    c_file,
+                    self,
+                    name.as_deref(),
+                    |name| {
+                        if glsl {
+                            name.to_string()
+                        } else {
+                            format!("struct {}", name)
+                        }
+                    },
+                    |name| {
+                        let inner = members_to_c(fields, dialect, c_file)?;
+                        let repr = match repr {
+                            Some(repr) => format!("{} ", repr.to_c(dialect, c_file)?),
+                            None => "".to_string(),
+                        };
+                        if !glsl {
+                            c_file.forward_declaration(format!("struct {};", name));
+                        }
+                        c_file
+                            .type_definition(format!("struct {}{} {{\n{}\n}};", repr, name, inner));
+                        Ok(())
+                    },
+                )
+            }
+
+            CType::BitField { name, repr, fields } => {
+                check_unique_fields(
+                    fields
+                        .iter()
+                        .map(|(name, _)| name)
+                        .filter(|name| !name.is_empty()),
+                )?;
+                for (field, (ty, width)) in fields {
+                    check_bit_field(field, ty, *width)?;
+                }
+                intern(
+                    c_file,
+                    self,
+                    name.as_deref(),
+                    |name| format!("struct {}", name),
+                    |name| {
+                        let inner = fields
+                            .iter()
+                            .map(|(name, (ty, width))| {
+                                Ok(format!(
+                                    "{}: {};",
+                                    ty.declare(name, dialect, c_file)?,
+                                    width
+                                ))
+                            })
+                            .collect::<CResult<Vec<_>>>()?
+                            .join("\n");
+                        let repr = match repr {
+                            Some(repr) => format!("{} ", repr.to_c(dialect, c_file)?),
+                            None => "".to_string(),
+                        };
+                        c_file.forward_declaration(format!("struct {};", name));
+                        c_file
+                            .type_definition(format!("struct {}{} {{\n{}\n}};", repr, name, inner));
+                        Ok(())
+                    },
+                )
+            }
+
+            CType::Union { name, fields } => {
+                check_union_dialect(dialect)?;
+                check_unique_fields(fields.iter().map(|(name, _)| name))?;
+                intern(
+                    c_file,
+                    self,
+                    name.as_deref(),
+                    |name| format!("union {}", name),
+                    |name| {
+                        let inner = members_to_c(fields, dialect, c_file)?;
+                        c_file.forward_declaration(format!("union {};", name));
+                        c_file.type_definition(format!("union {} {{\n{}\n}};", name, inner));
+                        Ok(())
+                    },
+                )
+            }
+
+            CType::Enum {
+                name,
+                underlying,
+                variants,
+            } => {
+                check_unique_fields(variants.iter().map(|(name, _)| name))?;
+                intern(
+                    c_file,
+                    self,
+                    Some(name),
+                    |name| format!("enum {}", name),
+                    |c_name| {
+                        define_enum(
+                            name,
+                            c_name,
+                            underlying.as_deref(),
+                            variants,
+                            dialect,
+                            c_file,
+                        )
+                    },
+                )
+            }
+
+            CType::TaggedUnion { name, variants } => {
+                check_union_dialect(dialect)?;
+                check_unique_fields(variants.iter().map(|(name, _)| name))?;
+                intern(
+                    c_file,
+                    self,
+                    Some(name),
+                    |name| format!("struct {}", name),
+                    |c_name| define_tagged_union(name, c_name, variants, dialect, c_file),
+                )
+            }
+
+            CType::StructRef(name) => {
+                let name = c_file.c_name(name);
+                c_file.forward_declaration(format!("struct {};", name));
+                Ok(format!("struct {}", name))
+            }
+
+            CType::Typedef { name, ty } => intern(
+                c_file,
+                self,
+                Some(name),
+                |name| name.to_string(),
+                |name| {
+                    let ty = ty.declare(name, dialect, c_file)?;
+                    c_file.type_definition(format!("typedef {};", ty));
+                    Ok(())
+                },
+            ),
+
+            CType::Array { .. }
+            | CType::Pointer { .. }
+            | CType::Const { .. }
+            | CType::FunctionPointer { .. }
+            | CType::OpenCLExtension(OpenCLType::AddressSpace { .. }) => {
+                self.declare("", dialect, c_file)
+            }
+
+            _ => Err(CodegenError::UnsupportedType {
+                ty: self.clone(),
+                dialect,
+            }),
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_struct_field_order() {
+        let context = Context::standard("m".to_string());
+        let ty = CType::Struct {
+            name: None,
+            repr: Some(Repr::Packed),
+            fields: vec![("z".to_string(), CType::U8), ("a".to_string(), CType::I32)],
+        };
+        ty.to_c(CDialect::Standard, &context).unwrap();
+        let definition = context.c_file.lock().unwrap().type_definitions[0].clone();
+        assert!(definition.ends_with("{\nuint8_t z;\nint32_t a;\n};"));
+
+        let ty = CType::Struct {
+            name: None,
+            repr: None,
+            fields: vec![("a".to_string(), CType::U8), ("a".to_string(), CType::I32)],
+        };
+        assert_eq!(
+            ty.to_c(CDialect::Standard, &context),
+            Err(CodegenError::DuplicateField("a".to_string()))
+        );
+    }
+
+    #[test]
+    fn test_type_interning() {
+        let context = Context::standard("m".to_string());
+        let anonymous = CType::Struct {
+            name: None,
+            repr: None,
+            fields: vec![("x".to_string(), CType::I32)],
+        };
+        let a = anonymous.to_c(CDialect::Standard, &context).unwrap();
+        let b = anonymous.to_c(CDialect::Standard, &context).unwrap();
+        assert_eq!(a, b);
+
+        let callback = CType::FunctionPointer {
+            return_ty: Box::new(CType::Void),
+            arguments: vec![anonymous.clone()],
+        };
+        assert_eq!(
+            callback.to_c(CDialect::Standard, &context).unwrap(),
+            format!("void (*)({})", a)
+        );
+        assert_eq!(context.c_file.lock().unwrap().type_definitions.len(), 1);
+
+        let list = CType::Struct {
+            name: Some("list".to_string()),
+            repr: None,
+            fields: vec![
+                ("value".to_string(), CType::I32),
+                (
+                    "next".to_string(),
+                    CType::Pointer {
+                        ty: Box::new(CType::StructRef("list".to_string())),
+                    },
+                ),
+            ],
+        };
+        assert_eq!(
+            list.to_c(CDialect::Standard, &context).unwrap(),
+            "struct m_MM_list"
+        );
+        let alias = CType::Typedef {
+            name: "list_t".to_string(),
+            ty: Box::new(list.clone()),
+        };
+        assert_eq!(
+            alias.to_c(CDialect::Standard, &context).unwrap(),
+            "m_MM_list_X5F_t"
+        );
+        {
+            let c_file = context.c_file.lock().unwrap();
+            assert_eq!(
+                c_file.type_definitions[1..],
+                [
+                    "struct m_MM_list {\nint32_t value;\nstruct m_MM_list *next;\n};",
+                    "typedef struct m_MM_list m_MM_list_X5F_t;",
+                ]
+            );
+            assert_eq!(
+                c_file
+                    .forward_declarations
+                    .iter()
+                    .filter(|d| *d == "struct m_MM_list;")
+                    .count(),
+                1
+            );
+        }
+
+        let other = CType::Struct {
+            name: Some("list".to_string()),
+            repr: None,
+            fields: vec![],
+        };
+        assert_eq!(
+            other.to_c(CDialect::Standard, &context),
+            Err(CodegenError::ConflictingType("list".to_string()))
+        );
+    }
+
+    #[test]
+    fn test_bit_field() {
+        let context = Context::standard("m".to_string());
+        let flags = CType::BitField {
+            name: Some("flags".to_string()),
+            repr: Some(Repr::Packed),
+            fields: vec![
+                ("ready".to_string(), (CType::U8, 1)),
+                ("".to_string(), (CType::U8, 0)),
+                ("mode".to_string(), (CType::U8, 3)),
+                ("".to_string(), (CType::U8, 4)),
+                ("count".to_string(), (CType::U16, 12)),
+            ],
+        };
+        assert_eq!(
+            flags.to_c(CDialect::Standard, &context).unwrap(),
+            "struct m_MM_flags"
+        );
+        assert_eq!(
+            context.c_file.lock().unwrap().type_definitions,
+            ["struct __attribute__ ((packed)) m_MM_flags {\n\
+              uint8_t ready: 1;\n\
+              uint8_t: 0;\n\
+              uint8_t mode: 3;\n\
+              uint8_t: 4;\n\
+              uint16_t count: 12;\n\
+              };"]
+        );
+
+        let too_wide = CType::BitField {
+            name: None,
+            repr: None,
+            fields: vec![("x".to_string(), (CType::U8, 9))],
+        };
+        assert_eq!(
+            too_wide.to_c(CDialect::Standard, &context),
+            Err(CodegenError::InvalidBitFieldWidth {
+                field: "x".to_string(),
+                width: 9,
+                max: 8
+            })
+        );
+        let not_integer = CType::BitField {
+            name: None,
+            repr: None,
+            fields: vec![("x".to_string(), (CType::F32, 1))],
+        };
+        assert_eq!(
+            not_integer.to_c(CDialect::Standard, &context),
+            Err(CodegenError::InvalidBitFieldType {
+                field: "x".to_string(),
+                ty: CType::F32
+            })
+        );
+    }
+
+    #[test]
+    fn test_enum() {
+        let context = Context::standard("m".to_string());
+        let color = CType::Enum {
+            name: "color".to_string(),
+            underlying: None,
+            variants: vec![
+                ("red".to_string(), None),
+                ("green".to_string(), Some(4)),
+                ("blue".to_string(), None),
+            ],
+        };
+        assert_eq!(
+            color.to_c(CDialect::Standard, &context).unwrap(),
+            "enum m_MM_color"
+        );
+        assert_eq!(
+            context.c_file.lock().unwrap().type_definitions,
+            ["enum m_MM_color {\n\
+              m_MM_color_X2E_red,\n\
+              m_MM_color_X2E_green = 4,\n\
+              m_MM_color_X2E_blue\n\
+              };"]
+        );
+
+        let small = CType::Enum {
+            name: "small".to_string(),
+            underlying: Some(Box::new(CType::U8)),
+            variants: vec![("a".to_string(), Some(255)), ("b".to_string(), None)],
+        };
+        assert_eq!(
+            small.to_c(CDialect::Standard, &context),
+            Err(CodegenError::EnumeratorOutOfRange {
+                variant: "b".to_string(),
+                value: 256,
+                ty: CType::U8
+            })
+        );
+
+        // without an underlying type the enumerators are `int` constants
+        let wide = |name: &str, underlying| CType::Enum {
+            name: name.to_string(),
+            underlying,
+            variants: vec![("min".to_string(), Some(i64::MIN))],
+        };
+        assert_eq!(
+            wide("wide", None).to_c(CDialect::Standard, &context),
+            Err(CodegenError::EnumeratorOutOfRange {
+                variant: "min".to_string(),
+                value: i64::MIN as i128,
+                ty: CType::I32
+            })
+        );
+        assert_eq!(
+            CType::Enum {
+                name: "none".to_string(),
+                underlying: None,
+                variants: vec![],
+            }
+            .to_c(CDialect::Standard, &context),
+            Err(CodegenError::NoVariants("none".to_string()))
+        );
+        wide("fixed", Some(Box::new(CType::I64)))
+            .to_c(CDialect::Standard, &context)
+            .unwrap();
+        assert!(
+            context
+                .c_file
+                .lock()
+                .unwrap()
+                .type_definitions
+                .last()
+                .unwrap()
+                .contains("m_MM_fixed_X2E_min = -9223372036854775807 - 1\n")
+        );
+    }
+
+    #[test]
+    fn test_declarators() {
+        let context = Context::standard("m".to_string());
+        let declare =
+            |ty: CType, name: &str| ty.declare(name, CDialect::Standard, &context).unwrap();
+        let int = || Box::new(CType::I32);
+        let char_ptr = || CType::Pointer {
+            ty: Box::new(CType::I8),
+        };
+
+        assert_eq!(
+            declare(
+                CType::Array {
+                    ty: int(),
+                    size: Some(4)
+                },
+                "a"
+            ),
+            "int32_t a[4]"
+        );
+        assert_eq!(
+            declare(
+                CType::Pointer {
+                    ty: Box::new(CType::Array {
+                        ty: int(),
+                        size: Some(4)
+                    })
+                },
+                "p"
+            ),
+            "int32_t (*p)[4]"
+        );
+        assert_eq!(
+            declare(
+                CType::Pointer {
+                    ty: Box::new(CType::Array {
+                        ty: int(),
+                        size: Some(4)
+                    })
+                },
+                ""
+            ),
+            "int32_t (*)[4]"
+        );
+        assert_eq!(
+            declare(
+                CType::FunctionPointer {
+                    return_ty: Box::new(CType::Void),
+                    arguments: vec![CType::I32],
+                },
+                "fp"
+            ),
+            "void (*fp)(int32_t)"
+        );
+        assert_eq!(
+            declare(
+                CType::Array {
+                    ty: Box::new(CType::Const {
+                        ty: Box::new(char_ptr())
+                    }),
+                    size: None
+                },
+                "argv"
+            ),
+            "int8_t *const argv[]"
+        );
+        assert_eq!(
+            declare(
+                CType::Pointer {
+                    ty: Box::new(CType::Const { ty: int() })
+                },
+                "p"
+            ),
+            "const int32_t *p"
+        );
+        assert_eq!(
+            declare(
+                CType::Array {
+                    ty: Box::new(CType::FunctionPointer {
+                        return_ty: Box::new(char_ptr()),
+                        arguments: vec![],
+                    }),
+                    size: Some(2)
+                },
+                "table"
+            ),
+            "int8_t *(*table[2])(void)"
+        );
+    }
+
+    #[test]
+    fn test_modern_c_types() {
+        let context = Context::standard("m".to_string());
+        for (ty, spelling) in [
+            (ModernCTypes::F16, "_Float16"),
+            (ModernCTypes::I128, "__int128"),
+            (ModernCTypes::U128, "unsigned __int128"),
+            (ModernCTypes::F128, "m_MM__X5F_Float128"),
+        ] {
+            assert_eq!(
+                CType::ModernCExtension(ty)
+                    .to_c(CDialect::Standard, &context)
+                    .unwrap(),
+                spelling
+            );
+        }
+        CType::ModernCExtension(ModernCTypes::F128)
+            .to_c(CDialect::Standard, &context)
+            .unwrap();
+        // the int128 guard is shared by the signed and unsigned type, the
+        // guards stay out of the type definitions
+        let c_file = context.c_file.lock().unwrap();
+        assert_eq!(c_file.preamble.len(), 3);
+        assert_eq!(
+            c_file.type_definitions,
+            ["#if defined(__FLT128_MAX__)\n\
+              typedef _Float128 m_MM__X5F_Float128;\n\
+              #else\n\
+              typedef __float128 m_MM__X5F_Float128;\n\
+              #endif"]
+        );
+        // the typedef is module-escaped, so modules do not collide
+        let other = Context::standard("n".to_string());
+        assert_eq!(
+            CType::ModernCExtension(ModernCTypes::F128)
+                .to_c(CDialect::Standard, &other)
+                .unwrap(),
+            "n_MM__X5F_Float128"
+        );
+    }
+}
@@ -1,15 +1,32 @@
-use std::collections::HashMap;
-
-use crate::escape::{self, get_temp_variable_name};
-
-use super::{ToC, c_stmt::Context, c_type::CType};
+use super::{
+    CDialect, KernelTarget, ToC,
+    c_arch::DataModel,
+    c_error::{CResult, CodegenError},
+    c_stmt::Context,
+    c_type::{CType, ModernCTypes, check_union_dialect, check_unique_fields, enumerator},
+};
 
 #[derive(Debug, Clone)]
 pub enum IntegerSuffix {
     None,
-    U32,
-    I64,
-    U64,
+    U,
+    L,
+    UL,
+    LL,
+    ULL,
+    /// the shortest suffix giving the literal the type of `ty` after the
+    /// integer promotions, the value has to fit in `ty`
+    Typed(CType),
+}
+
+/// base an integer literal is written in
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Radix {
+    Decimal,
+    Hex,
+    Octal,
+    /// `0b`, C23 and C++14
+    Binary,
 }
 
 #[derive(Debug, Clone)]
@@ -17,46 +34,452 @@ pub enum FloatSuffix {
     None,
     F32,
     F64,
+    /// `_Float16`, emitted as a cast since the `f16` suffix is not portable
+    F16,
+    /// `_Float128`/`__float128`, emitted as a cast like `F16`
+    F128,
 }
 
 #[derive(Debug, Clone)]
 pub enum CLiteral {
-    Int(usize, IntegerSuffix),
+    /// an integer in the range of `int64_t` or `uint64_t`
+    Int(i128, IntegerSuffix, Radix),
+    /// C has no 128-bit integer literals, these are built from 64-bit halves
+    Int128(i128),
+    UInt128(u128),
     Float(f64, FloatSuffix),
-    CChar(char),
-    CString(String),
+    /// a float printed in C99 hexadecimal notation, e.g. `0x1.8p+1`
+    HexFloat(f64, FloatSuffix),
+    CChar(char, StringPrefix),
+    CString(String, StringPrefix),
+    /// a narrow string literal of arbitrary bytes, e.g. embedded binary data
+    ///
+    /// long data is split into pieces for MSVC, but the total length is not
+    /// helped by that: C only guarantees 4095 characters in the concatenated
+    /// literal, larger blobs are portable only as an array initializer
+    Bytes(Vec<u8>),
 }
 
-impl ToC for CLiteral {
-    fn to_c(&self, dialect: super::CDialect, _c: &Context) -> Option<String> {
+/// encoding prefix of string and character literals
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum StringPrefix {
+    /// the execution character set, strings are emitted as UTF-8
+    None,
+    /// `u8`
+    Utf8,
+    /// `u`, `char16_t`
+    Utf16,
+    /// `U`, `char32_t`
+    Utf32,
+    /// `L`, `wchar_t`
+    Wide,
+}
+
+impl StringPrefix {
+    fn spelling(self) -> &'static str {
         match self {
-            CLiteral::Int(v, suffix) => {
-                let suffix = if dialect == super::CDialect::Standard {
-                    match suffix {
-                        IntegerSuffix::None => "".to_string(),
-                        IntegerSuffix::U32 => "u".to_string(),
-                        IntegerSuffix::I64 => "l".to_string(),
-                        IntegerSuffix::U64 => "ul".to_string(),
-                    }
+            StringPrefix::None => "",
+            StringPrefix::Utf8 => "u8",
+            StringPrefix::Utf16 => "u",
+            StringPrefix::Utf32 => "U",
+            StringPrefix::Wide => "L",
+        }
+    }
+
+    /// narrow literals hold UTF-8 bytes, the others whole characters
+    fn is_narrow(self) -> bool {
+        matches!(self, StringPrefix::None | StringPrefix::Utf8)
+    }
+
+    /// bytes of one code unit, `wchar_t` taken at its widest
+    fn unit_size(self) -> usize {
+        match self {
+            StringPrefix::None | StringPrefix::Utf8 => 1,
+            StringPrefix::Utf16 => 2,
+            StringPrefix::Utf32 | StringPrefix::Wide => 4,
+        }
+    }
+}
+
+/// MSVC rejects a single literal of more than 2048 bytes including the
+/// terminator, so longer strings are split into adjacent literals; the C
+/// limit of 4095 characters applies after concatenation and is not lifted
+/// by splitting
+const MAX_LITERAL_PIECE: usize = 2048;
+
+/// `b` as it appears between `quote`s, `after_question` avoids forming
+/// trigraphs like `??=`
+fn escape_ascii(b: u8, quote: u8, after_question: bool) -> String {
+    match b {
+        b'\\' => "\\\\".to_string(),
+        b'\n' => "\\n".to_string(),
+        b'\t' => "\\t".to_string(),
+        b'\r' => "\\r".to_string(),
+        b'?' if after_question => "\\?".to_string(),
+        _ if b == quote => format!("\\{}", b as char),
+        0x20..=0x7e => (b as char).to_string(),
+        // octal escapes end after three digits, unlike hex escapes which
+        // would swallow a following hex digit
+        _ => format!("\\{:03o}", b),
+    }
+}
+
+/// the escaped characters of `text`, one entry per source character
+fn escape_text(text: &str, prefix: StringPrefix, quote: u8) -> Vec<String> {
+    let mut out = vec![];
+    let mut after_question = false;
+    for c in text.chars() {
+        if c.is_ascii() {
+            out.push(escape_ascii(c as u8, quote, after_question));
+        } else if prefix.is_narrow() {
+            let mut buf = [0; 4];
+            let bytes = c.encode_utf8(&mut buf).bytes();
+            out.push(bytes.map(|b| format!("\\{:03o}", b)).collect());
+        } else if (c as u32) < 0xa0 {
+            // universal character names cannot spell C1 controls
+            out.push(format!("\\{:03o}", c as u32));
+        } else if (c as u32) <= 0xffff {
+            out.push(format!("\\u{:04x}", c as u32));
+        } else {
+            out.push(format!("\\U{:08x}", c as u32));
+        }
+        after_question = c == '?';
+    }
+    out
+}
+
+/// adjacent literals of at most `MAX_LITERAL_PIECE` bytes each, an escape
+/// is counted by its length in the source which is never less than the
+/// bytes it stands for
+fn string_literal(prefix: StringPrefix, escaped: Vec<String>) -> String {
+    let unit = prefix.unit_size();
+    let mut pieces = vec![];
+    let (mut piece, mut size) = (String::new(), 0);
+    for c in escaped {
+        let bytes = c.len().max(unit);
+        if size + bytes + unit > MAX_LITERAL_PIECE {
+            pieces.push(std::mem::take(&mut piece));
+            size = 0;
+        }
+        piece.push_str(&c);
+        size += bytes;
+    }
+    pieces.push(piece);
+    pieces
+        .iter()
+        .map(|piece| format!("{}\"{}\"", prefix.spelling(), piece))
+        .collect::<Vec<_>>()
+        .join("\n")
+}
+
+/// OpenCL C is C99 and has only plain literals, GLSL has no strings at all
+fn check_string_dialect(prefix: StringPrefix, dialect: CDialect) -> CResult<()> {
+    match dialect {
+        CDialect::Kernel(KernelTarget::GLSL) => Err(CodegenError::UnsupportedFeature {
+            feature: "string literals",
+            dialect,
+        }),
+        CDialect::Kernel(KernelTarget::OpenCL) if prefix != StringPrefix::None => {
+            Err(CodegenError::UnsupportedFeature {
+                feature: "prefixed string literals",
+                dialect,
+            })
+        }
+        _ => Ok(()),
+    }
+}
+
+/// exact C99 hexadecimal spelling of a finite `v`
+fn hex_float(v: f64) -> String {
+    let sign = if v.is_sign_negative() { "-" } else { "" };
+    let bits = v.abs().to_bits();
+    let (exponent, mantissa) = ((bits >> 52) as i64, bits & ((1 << 52) - 1));
+    if exponent == 0 && mantissa == 0 {
+        return format!("{}0x0p+0", sign);
+    }
+    // subnormals have no implicit leading one
+    let (lead, exponent) = if exponent == 0 {
+        (0, -1022)
+    } else {
+        (1, exponent - 1023)
+    };
+    let digits = format!("{:013x}", mantissa);
+    let digits = digits.trim_end_matches('0');
+    let dot = if digits.is_empty() { "" } else { "." };
+    format!("{}0x{}{}{}p{:+}", sign, lead, dot, digits, exponent)
+}
+
+/// a float literal which reads back as exactly `v`
+fn float_literal(
+    v: f64,
+    suffix: &FloatSuffix,
+    hex: bool,
+    dialect: CDialect,
+    c: &Context,
+) -> CResult<String> {
+    let glsl = dialect == CDialect::Kernel(KernelTarget::GLSL);
+    if hex && glsl {
+        return Err(CodegenError::UnsupportedFeature {
+            feature: "hexadecimal float literals",
+            dialect,
+        });
+    }
+    // finite values must stay finite in the narrower types, a half rounds
+    // to infinity from 65520 on
+    let overflow = match suffix {
+        FloatSuffix::F32 => Some(CType::F32).filter(|_| (v as f32).is_infinite()),
+        FloatSuffix::F16 => {
+            Some(CType::ModernCExtension(ModernCTypes::F16)).filter(|_| v.abs() >= 65520.0)
+        }
+        _ => None,
+    };
+    if let Some(ty) = overflow.filter(|_| v.is_finite()) {
+        return Err(CodegenError::FloatOutOfRange {
+            value: format!("{:?}", v),
+            ty,
+        });
+    }
+    let cast = match suffix {
+        FloatSuffix::F16 => Some(CType::ModernCExtension(ModernCTypes::F16).to_c(dialect, c)?),
+        FloatSuffix::F128 => Some(CType::ModernCExtension(ModernCTypes::F128).to_c(dialect, c)?),
+        _ => None,
+    };
+    let literal = if !v.is_finite() {
+        let positive = !v.is_sign_negative();
+        if glsl {
+            // GLSL has no infinity or NaN constants, build them from their bits
+            let bits = match (v.is_nan(), positive) {
+                (true, _) => "0x7fc00000u",
+                (false, true) => "0x7f800000u",
+                (false, false) => "0xff800000u",
+            };
+            format!("uintBitsToFloat({})", bits)
+        } else {
+            // OpenCL C has the macros built in
+            if dialect != CDialect::Kernel(KernelTarget::OpenCL) {
+                c.include("math.h");
+            }
+            match (v.is_nan(), positive) {
+                (true, _) => "NAN".to_string(),
+                (false, true) => "INFINITY".to_string(),
+                (false, false) => "-INFINITY".to_string(),
+            }
+        }
+    } else {
+        // `{:?}` is the shortest spelling that round-trips and always has a
+        // `.` or an exponent, so C does not read an integer
+        let (digits, suffix) = match suffix {
+            FloatSuffix::F32 => {
+                let v = v as f32;
+                let digits = if hex {
+                    hex_float(v as f64)
                 } else {
-                    "".to_string()
+                    format!("{:?}", v)
                 };
-                Some(format!("{}{}", v, suffix))
-            }
-            CLiteral::Float(v, suffix) => {
-                let suffix = if dialect == super::CDialect::Standard {
-                    match suffix {
-                        FloatSuffix::None => "".to_string(),
-                        FloatSuffix::F32 => "f".to_string(),
-                        FloatSuffix::F64 => "l".to_string(),
-                    }
+                (digits, if glsl { "" } else { "f" })
+            }
+            FloatSuffix::F64 if glsl => (format!("{:?}", v), "lf"),
+            _ if hex => (hex_float(v), ""),
+            _ => (format!("{:?}", v), ""),
+        };
+        format!("{}{}", digits, suffix)
+    };
+    Ok(match cast {
+        Some(ty) => format!("(({}){})", ty, literal),
+        None => literal,
+    })
+}
+
+/// `unsigned __int128` expression of `v`
+fn u128_to_c(v: u128) -> String {
+    let (hi, lo) = ((v >> 64) as u64, v as u64);
+    if hi == 0 {
+        format!("((unsigned __int128)0x{:x}ull)", lo)
+    } else {
+        format!("(((unsigned __int128)0x{:x}ull << 64) | 0x{:x}ull)", hi, lo)
+    }
+}
+
+/// suffix of an integer literal of type `ty`, checking that `v` fits
+fn typed_suffix(v: i128, ty: &CType, dialect: CDialect, c: &Context) -> CResult<IntegerSuffix> {
+    let (Some(bits), Some((min, max))) = (ty.integer_bits(), ty.integer_range()) else {
+        return Err(CodegenError::UnsupportedType {
+            ty: ty.clone(),
+            dialect,
+        });
+    };
+    if !(min..=max).contains(&v) {
+        return Err(CodegenError::IntegerOutOfRange {
+            value: v,
+            ty: ty.clone(),
+        });
+    }
+    let signed = min < 0;
+    Ok(match (bits, signed) {
+        (64, _) if dialect == CDialect::Kernel(KernelTarget::GLSL) => {
+            return Err(CodegenError::UnsupportedType {
+                ty: ty.clone(),
+                dialect,
+            });
+        }
+        // `long` is only known to be 64-bit in OpenCL C and on LP64 targets
+        (64, _) => {
+            let long = dialect == CDialect::Kernel(KernelTarget::OpenCL)
+                || c.c_file.lock().unwrap().data_model == Some(DataModel::LP64);
+            match (long, signed) {
+                (true, true) => IntegerSuffix::L,
+                (true, false) => IntegerSuffix::UL,
+                (false, true) => IntegerSuffix::LL,
+                (false, false) => IntegerSuffix::ULL,
+            }
+        }
+        (32, false) => IntegerSuffix::U,
+        // narrower types are promoted to `int` anyway
+        _ => IntegerSuffix::None,
+    })
+}
+
+fn int_literal(
+    v: i128,
+    suffix: &IntegerSuffix,
+    radix: Radix,
+    dialect: CDialect,
+    c: &Context,
+) -> CResult<String> {
+    let suffix = match suffix {
+        IntegerSuffix::Typed(ty) => typed_suffix(v, ty, dialect, c)?,
+        suffix => suffix.clone(),
+    };
+    // GLSL has 32-bit `int` and `uint` only
+    let glsl = dialect == CDialect::Kernel(KernelTarget::GLSL);
+    let (signed, unsigned) = if glsl {
+        (CType::I32, CType::U32)
+    } else {
+        (CType::I64, CType::U64)
+    };
+    let (smin, smax) = signed.integer_range().unwrap();
+    let (_, umax) = unsigned.integer_range().unwrap();
+    // an unsuffixed decimal literal is signed, in other bases it may take
+    // the unsigned type; the minimum is spelled as a subtraction below
+    let (min, max, ty) = match suffix {
+        IntegerSuffix::U | IntegerSuffix::UL | IntegerSuffix::ULL => (0, umax, unsigned),
+        IntegerSuffix::L | IntegerSuffix::LL => (smin, smax, signed),
+        IntegerSuffix::None if radix == Radix::Decimal => (smin, smax, signed),
+        IntegerSuffix::None if v < 0 => (smin, umax, signed),
+        IntegerSuffix::None => (smin, umax, unsigned),
+        IntegerSuffix::Typed(_) => unreachable!("resolved above"),
+    };
+    if !(min..=max).contains(&v) {
+        return Err(CodegenError::IntegerOutOfRange { value: v, ty });
+    }
+    let opencl = dialect == CDialect::Kernel(KernelTarget::OpenCL);
+    let suffix = match suffix {
+        IntegerSuffix::None => "",
+        IntegerSuffix::L | IntegerSuffix::LL if glsl => "",
+        IntegerSuffix::U | IntegerSuffix::UL | IntegerSuffix::ULL if glsl => "u",
+        IntegerSuffix::U => "u",
+        IntegerSuffix::L => "l",
+        IntegerSuffix::UL => "ul",
+        // `long` is 64-bit and `long long` reserved in OpenCL C
+        IntegerSuffix::LL if opencl => "l",
+        IntegerSuffix::ULL if opencl => "ul",
+        IntegerSuffix::LL => "ll",
+        IntegerSuffix::ULL => "ull",
+        IntegerSuffix::Typed(_) => unreachable!("resolved above"),
+    };
+    let digits = |m: u128| -> CResult<String> {
+        Ok(match radix {
+            Radix::Decimal => format!("{}", m),
+            Radix::Hex => format!("0x{:x}", m),
+            Radix::Octal if m == 0 => "0".to_string(),
+            Radix::Octal => format!("0{:o}", m),
+            Radix::Binary => {
+                if matches!(
+                    dialect,
+                    CDialect::Kernel(KernelTarget::GLSL | KernelTarget::OpenCL)
+                ) {
+                    return Err(CodegenError::UnsupportedFeature {
+                        feature: "binary integer literals",
+                        dialect,
+                    });
+                }
+                format!("0b{:b}", m)
+            }
+        })
+    };
+    let magnitude = v.unsigned_abs();
+    if v >= 0 {
+        Ok(format!("{}{}", digits(magnitude)?, suffix))
+    } else if is_minimum(v) {
+        // the magnitude of the minimum does not fit in the signed type, so
+        // `-2147483648` would negate a wider or unsigned literal
+        Ok(format!("-{}{} - 1", digits(magnitude - 1)?, suffix))
+    } else {
+        Ok(format!("-{}{}", digits(magnitude)?, suffix))
+    }
+}
+
+/// `INT32_MIN` and `INT64_MIN`, written as `-MAX - 1`
+fn is_minimum(v: i128) -> bool {
+    v == i32::MIN as i128 || v == i64::MIN as i128
+}
+
+impl ToC for CLiteral {
+    fn to_c(&self, dialect: super::CDialect, c: &Context) -> CResult<String> {
+        match self {
+            CLiteral::Int(v, suffix, radix) => int_literal(*v, suffix, *radix, dialect, c),
+            CLiteral::Int128(v) => {
+                let int128 = CType::ModernCExtension(ModernCTypes::I128).to_c(dialect, c)?;
+                if *v >= 0 {
+                    Ok(format!("(({}){})", int128, u128_to_c(*v as u128)))
                 } else {
-                    "".to_string()
+                    // -(v + 1) - 1 also covers the minimum value
+                    let magnitude = (-(v + 1)) as u128;
+                    Ok(format!("(-(({}){}) - 1)", int128, u128_to_c(magnitude)))
+                }
+            }
+            CLiteral::UInt128(v) => {
+                CType::ModernCExtension(ModernCTypes::U128).to_c(dialect, c)?;
+                Ok(u128_to_c(*v))
+            }
+            CLiteral::Float(v, suffix) => float_literal(*v, suffix, false, dialect, c),
+            CLiteral::HexFloat(v, suffix) => float_literal(*v, suffix, true, dialect, c),
+            CLiteral::CChar(v, prefix) => {
+                check_string_dialect(*prefix, dialect)?;
+                // one code unit: ASCII for narrow literals, no surrogate pairs in `u''`
+                let fits = match prefix {
+                    StringPrefix::None | StringPrefix::Utf8 => v.is_ascii(),
+                    StringPrefix::Utf16 => (*v as u32) <= 0xffff,
+                    StringPrefix::Utf32 | StringPrefix::Wide => true,
                 };
-                Some(format!("{}{}", v, suffix))
+                if !fits {
+                    return Err(CodegenError::InvalidCharLiteral {
+                        value: *v,
+                        prefix: *prefix,
+                    });
+                }
+                // `u8''` is C23, an ASCII character is the same without it
+                let prefix = match prefix {
+                    StringPrefix::Utf8 => StringPrefix::None,
+                    prefix => *prefix,
+                };
+                let escaped = escape_text(&v.to_string(), prefix, b'\'').concat();
+                Ok(format!("{}'{}'", prefix.spelling(), escaped))
+            }
+            CLiteral::CString(v, prefix) => {
+                check_string_dialect(*prefix, dialect)?;
+                Ok(string_literal(*prefix, escape_text(v, *prefix, b'"')))
+            }
+            CLiteral::Bytes(bytes) => {
+                check_string_dialect(StringPrefix::None, dialect)?;
+                let mut escaped = vec![];
+                let mut after_question = false;
+                for b in bytes {
+                    escaped.push(escape_ascii(*b, b'"', after_question));
+                    after_question = *b == b'?';
+                }
+                Ok(string_literal(StringPrefix::None, escaped))
             }
-            CLiteral::CChar(v) => Some(format!("'{}'", v)),
-            CLiteral::CString(v) => Some(format!("\"{}\"", v)),
         }
     }
 }
@@ -64,114 +487,850 @@ impl ToC for CLiteral {
 pub enum CValue {
     Literal(CLiteral),
     Variable(String),
+    /// an identifier defined outside of the module, printed without
+    /// escaping, e.g. C library functions or GLSL built-ins like `vec4`
+    Extern(String),
+    /// `Enumerator(enum, variant)` refers to a variant of a `CType::Enum`
+    Enumerator(String, String),
     // CType should be array of something
     Array(CType, Vec<CValue>),
-    Struct(HashMap<String, CValue>),
-    Union(HashMap<String, CValue>),
+    // designated initializers, emitted in the given order
+    Struct(Vec<(String, CValue)>),
+    Union(Vec<(String, CValue)>),
     Reference(Box<CValue>),
     Dereference(Box<CValue>),
     MemberAccess(Box<CValue>, String),
     IndexAccess(Box<CValue>, Box<CValue>),
     FunctionCall(Box<CValue>, Vec<CValue>),
-    BinOp(String, Box<CValue>, Box<CValue>),
-    PrefixOp(String, Box<CValue>),
-    PostfixOp(String, Box<CValue>),
+    BinOp(BinaryOp, Box<CValue>, Box<CValue>),
+    UnaryOp(UnaryOp, Box<CValue>),
+    /// `(ty)value`, a constructor call `ty(value)` in GLSL
+    Cast(CType, Box<CValue>),
+    SizeOf(CType),
+    AlignOf(CType),
+    /// `offsetof(ty, field)`, `ty` must be a struct with that field
+    OffsetOf(CType, String),
+    /// `cond ? then : otherwise`
+    Ternary(Box<CValue>, Box<CValue>, Box<CValue>),
 
     // compile with LLVM Enzyme Plugin
     AutoDiff(String, Vec<CValue>),
 }
 
+/// binding strength of C expressions, higher binds tighter
+mod precedence {
+    pub const ASSIGNMENT: u8 = 2;
+    pub const CONDITIONAL: u8 = 3;
+    pub const LOGICAL_OR: u8 = 4;
+    pub const UNARY: u8 = 15;
+    pub const POSTFIX: u8 = 16;
+    pub const PRIMARY: u8 = 17;
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum BinaryOp {
+    Add,
+    Sub,
+    Mul,
+    Div,
+    Rem,
+    Shl,
+    Shr,
+    BitAnd,
+    BitOr,
+    BitXor,
+    And,
+    Or,
+    Eq,
+    Ne,
+    Lt,
+    Le,
+    Gt,
+    Ge,
+    Assign,
+    AddAssign,
+    SubAssign,
+    MulAssign,
+    DivAssign,
+    RemAssign,
+    ShlAssign,
+    ShrAssign,
+    BitAndAssign,
+    BitOrAssign,
+    BitXorAssign,
+}
+
+impl BinaryOp {
+    pub fn spelling(self) -> &'static str {
+        use BinaryOp::*;
+        match self {
+            Add => "+",
+            Sub => "-",
+            Mul => "*",
+            Div => "/",
+            Rem => "%",
+            Shl => "<<",
+            Shr => ">>",
+            BitAnd => "&",
+            BitOr => "|",
+            BitXor => "^",
+            And => "&&",
+            Or => "||",
+            Eq => "==",
+            Ne => "!=",
+            Lt => "<",
+            Le => "<=",
+            Gt => ">",
+            Ge => ">=",
+            Assign => "=",
+            AddAssign => "+=",
+            SubAssign => "-=",
+            MulAssign => "*=",
+            DivAssign => "/=",
+            RemAssign => "%=",
+            ShlAssign => "<<=",
+            ShrAssign => ">>=",
+            BitAndAssign => "&=",
+            BitOrAssign => "|=",
+            BitXorAssign => "^=",
+        }
+    }
+
+    pub fn precedence(self) -> u8 {
+        use BinaryOp::*;
+        match self {
+            Mul | Div | Rem => 13,
+            Add | Sub => 12,
+            Shl | Shr => 11,
+            Lt | Le | Gt | Ge => 10,
+            Eq | Ne => 9,
+            BitAnd => 8,
+            BitXor => 7,
+            BitOr => 6,
+            And => 5,
+            Or => precedence::LOGICAL_OR,
+            _ => precedence::ASSIGNMENT,
+        }
+    }
+
+    /// assignments group right to left, every other binary operator left
+    /// to right
+    pub fn is_assignment(self) -> bool {
+        self.precedence() == precedence::ASSIGNMENT
+    }
+
+    /// the compound assignment `op=`, e.g. `+=` for `Add`
+    pub fn compound_assignment(self) -> Option<BinaryOp> {
+        use BinaryOp::*;
+        Some(match self {
+            Add => AddAssign,
+            Sub => SubAssign,
+            Mul => MulAssign,
+            Div => DivAssign,
+            Rem => RemAssign,
+            Shl => ShlAssign,
+            Shr => ShrAssign,
+            BitAnd => BitAndAssign,
+            BitOr => BitOrAssign,
+            BitXor => BitXorAssign,
+            _ => return None,
+        })
+    }
+
+    /// operand `child` of `self` is parenthesized although precedence
+    /// does not require it, the combinations `-Wparentheses` warns about
+    fn clarify(self, child: BinaryOp) -> bool {
+        use BinaryOp::*;
+        let bitwise = |op| matches!(op, BitAnd | BitOr | BitXor);
+        let arithmetic = |op| matches!(op, Add | Sub);
+        let comparison = |op| matches!(op, Eq | Ne | Lt | Le | Gt | Ge);
+        match self {
+            Or => child == And,
+            Shl | Shr => arithmetic(child),
+            op if bitwise(op) => {
+                (bitwise(child) && child != op) || arithmetic(child) || comparison(child)
+            }
+            op if comparison(op) => comparison(child),
+            _ => false,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum UnaryOp {
+    Neg,
+    Plus,
+    Not,
+    BitNot,
+    PreInc,
+    PreDec,
+    PostInc,
+    PostDec,
+}
+
+impl UnaryOp {
+    pub fn spelling(self) -> &'static str {
+        match self {
+            UnaryOp::Neg => "-",
+            UnaryOp::Plus => "+",
+            UnaryOp::Not => "!",
+            UnaryOp::BitNot => "~",
+            UnaryOp::PreInc | UnaryOp::PostInc => "++",
+            UnaryOp::PreDec | UnaryOp::PostDec => "--",
+        }
+    }
+
+    pub fn is_postfix(self) -> bool {
+        matches!(self, UnaryOp::PostInc | UnaryOp::PostDec)
+    }
+}
+
+impl CValue {
+    /// precedence of the outermost operator of the printed expression
+    pub fn precedence(&self) -> u8 {
+        use CValue::*;
+        match self {
+            Literal(
+                CLiteral::Float(v, FloatSuffix::None | FloatSuffix::F32 | FloatSuffix::F64)
+                | CLiteral::HexFloat(v, FloatSuffix::None | FloatSuffix::F32 | FloatSuffix::F64),
+            ) if v.is_sign_negative() && !v.is_nan() => precedence::UNARY,
+            Literal(CLiteral::Int(v, ..)) if is_minimum(*v) => BinaryOp::Sub.precedence(),
+            Literal(CLiteral::Int(v, ..)) if *v < 0 => precedence::UNARY,
+            BinOp(op, _, _) => op.precedence(),
+            UnaryOp(op, _) if op.is_postfix() => precedence::POSTFIX,
+            // GLSL casts are calls, which bind tighter, but need no special case
+            UnaryOp(..) | Reference(_) | Dereference(_) | Cast(..) | SizeOf(_) | AlignOf(_) => {
+                precedence::UNARY
+            }
+            MemberAccess(..) | IndexAccess(..) | FunctionCall(..) | OffsetOf(..) => {
+                precedence::POSTFIX
+            }
+            Ternary(..) => precedence::CONDITIONAL,
+            _ => precedence::PRIMARY,
+        }
+    }
+
+    /// print `self` as an operand that has to bind at least as tight as
+    /// `min`, parenthesized if it does not
+    fn operand(&self, min: u8, dialect: super::CDialect, context: &Context) -> CResult<String> {
+        let code = self.to_c(dialect, context)?;
+        if self.precedence() < min {
+            Ok(format!("({})", code))
+        } else {
+            Ok(code)
+        }
+    }
+
+    /// GLSL does not expose the memory layout of its types
+    fn check_layout_queries(dialect: super::CDialect) -> CResult<()> {
+        if dialect == CDialect::Kernel(KernelTarget::GLSL) {
+            return Err(CodegenError::UnsupportedFeature {
+                feature: "memory layout queries",
+                dialect,
+            });
+        }
+        Ok(())
+    }
+
+    /// the pointer operators have no meaning in GLSL
+    fn check_pointers(dialect: super::CDialect) -> CResult<()> {
+        if dialect == CDialect::Kernel(KernelTarget::GLSL) {
+            return Err(CodegenError::UnsupportedFeature {
+                feature: "pointer operators",
+                dialect,
+            });
+        }
+        Ok(())
+    }
+}
+
 impl ToC for CValue {
-    fn to_c(&self, dialect: super::CDialect, context: &Context) -> Option<String> {
+    fn to_c(&self, dialect: super::CDialect, context: &Context) -> CResult<String> {
         use CValue::*;
         match self {
             Literal(v) => v.to_c(dialect, context),
-            Variable(v) => Some(escape::string_to_escape_to_c_ansi_id(&context.module, v)),
+            Variable(v) => Ok(context.c_name(v)),
+            Extern(v) => Ok(v.clone()),
+            Enumerator(ty, variant) => Ok(enumerator(&context.module, ty, variant)),
             Array(ty, values) => {
+                if !matches!(ty, CType::Array { .. }) {
+                    return Err(CodegenError::NotAnArray(ty.clone()));
+                }
                 let values = values
                     .iter()
-                    .map(|value| value.to_c(dialect, context).unwrap())
-                    .collect::<Vec<_>>()
+                    .map(|value| value.to_c(dialect, context))
+                    .collect::<CResult<Vec<_>>>()?
                     .join(", ");
-                let name = get_temp_variable_name(&context.module);
-                if let CType::Array { ty, size } = ty {
-                    let size = size.map(|s| s.to_string()).unwrap_or("".to_string());
-                    let code = format!(
-                        "{} {}[{}] = {{ {} }};",
-                        ty.to_c(dialect, context).unwrap(),
-                        name,
-                        size,
-                        values
-                    );
-                    context.current_source.lock().unwrap().push_str(&code);
-                } else {
-                    panic!("Array type should be array");
-                }
-                Some(name)
+                let name = context.temp_name();
+                let code = format!(
+                    "{} = {{ {} }};\n",
+                    ty.declare(&name, dialect, context)?,
+                    values
+                );
+                context.current_source.lock().unwrap().push_str(&code);
+                Ok(name)
             }
             Struct(fields) => {
+                check_unique_fields(fields.iter().map(|(name, _)| name))?;
                 let fields = fields
                     .iter()
                     .map(|(name, value)| {
-                        format!(".{} = {}", name, value.to_c(dialect, context).unwrap())
+                        Ok(format!(".{} = {}", name, value.to_c(dialect, context)?))
                     })
-                    .collect::<Vec<_>>()
+                    .collect::<CResult<Vec<_>>>()?
                     .join(", ");
-                Some(format!("{{ {} }}", fields))
+                Ok(format!("{{ {} }}", fields))
             }
             Union(fields) => {
-                if context.dialect != super::CDialect::Standard {
-                    panic!("Union is not supported in {:?}", context.dialect);
-                }
+                check_union_dialect(dialect)?;
                 let fields = fields
                     .iter()
                     .map(|(name, value)| {
-                        format!(".{} = {}", name, value.to_c(dialect, context).unwrap())
+                        Ok(format!(".{} = {}", name, value.to_c(dialect, context)?))
                     })
-                    .collect::<Vec<_>>()
+                    .collect::<CResult<Vec<_>>>()?
                     .join(", ");
-                Some(format!("{{ {} }}", fields))
+                Ok(format!("{{ {} }}", fields))
             }
             Reference(value) => {
-                let value = value.to_c(dialect, context).unwrap();
-                Some(format!("(&{})", value))
+                Self::check_pointers(dialect)?;
+                let value = value.operand(precedence::UNARY, dialect, context)?;
+                Ok(format!("&{}", value))
             }
             Dereference(value) => {
-                let value = value.to_c(dialect, context).unwrap();
-                Some(format!("(*{})", value))
+                Self::check_pointers(dialect)?;
+                let value = value.operand(precedence::UNARY, dialect, context)?;
+                Ok(format!("*{}", value))
             }
             MemberAccess(value, member) => {
-                let value = value.to_c(dialect, context).unwrap();
-                Some(format!("({}.{})", value, member))
+                let value = value.operand(precedence::POSTFIX, dialect, context)?;
+                Ok(format!("{}.{}", value, member))
             }
             IndexAccess(value, index) => {
-                let value = value.to_c(dialect, context).unwrap();
-                let index = index.to_c(dialect, context).unwrap();
-                Some(format!("({}[{}])", value, index))
+                let value = value.operand(precedence::POSTFIX, dialect, context)?;
+                let index = index.to_c(dialect, context)?;
+                Ok(format!("{}[{}]", value, index))
             }
             FunctionCall(func, args) => {
-                let func = func.to_c(dialect, context).unwrap();
+                let func = func.operand(precedence::POSTFIX, dialect, context)?;
                 let args = args
                     .iter()
-                    .map(|arg| arg.to_c(dialect, context).unwrap())
-                    .collect::<Vec<_>>()
+                    .map(|arg| arg.to_c(dialect, context))
+                    .collect::<CResult<Vec<_>>>()?
                     .join(", ");
-                Some(format!("({}({}))", func, args))
+                Ok(format!("{}({})", func, args))
             }
             BinOp(op, lhs, rhs) => {
-                let lhs = lhs.to_c(dialect, context).unwrap();
-                let rhs = rhs.to_c(dialect, context).unwrap();
-                Some(format!("({} {} {})", lhs, op, rhs))
+                let p = op.precedence();
+                // the operand on the grouping side may have the same precedence
+                let (lhs_min, rhs_min) = if op.is_assignment() {
+                    (p + 1, p)
+                } else {
+                    (p, p + 1)
+                };
+                let side = |value: &CValue, min: u8| match value {
+                    BinOp(child, _, _) if op.clarify(*child) => {
+                        Ok(format!("({})", value.to_c(dialect, context)?))
+                    }
+                    _ => value.operand(min, dialect, context),
+                };
+                Ok(format!(
+                    "{} {} {}",
+                    side(lhs, lhs_min)?,
+                    op.spelling(),
+                    side(rhs, rhs_min)?
+                ))
+            }
+            UnaryOp(op, value) if op.is_postfix() => {
+                let value = value.operand(precedence::POSTFIX, dialect, context)?;
+                Ok(format!("{}{}", value, op.spelling()))
+            }
+            UnaryOp(op, value) => {
+                let mut value = value.operand(precedence::UNARY, dialect, context)?;
+                // `- -x` must not print as `--x`
+                let sign = &op.spelling()[..1];
+                if (sign == "-" || sign == "+") && value.starts_with(sign) {
+                    value = format!("({})", value);
+                }
+                Ok(format!("{}{}", op.spelling(), value))
+            }
+            Cast(ty, value) if dialect == CDialect::Kernel(KernelTarget::GLSL) => {
+                let ty = ty.declare("", dialect, context)?;
+                Ok(format!("{}({})", ty, value.to_c(dialect, context)?))
             }
-            PrefixOp(op, value) => {
-                let value = value.to_c(dialect, context).unwrap();
-                Some(format!("({}{})", op, value))
+            Cast(ty, value) => {
+                let ty = ty.declare("", dialect, context)?;
+                let value = value.operand(precedence::UNARY, dialect, context)?;
+                Ok(format!("({}){}", ty, value))
             }
-            PostfixOp(op, value) => {
-                let value = value.to_c(dialect, context).unwrap();
-                Some(format!("({}{})", value, op))
+            SizeOf(ty) => {
+                Self::check_layout_queries(dialect)?;
+                Ok(format!("sizeof({})", ty.declare("", dialect, context)?))
             }
-            AutoDiff(_op, _args) => todo!(),
+            AlignOf(ty) => {
+                Self::check_layout_queries(dialect)?;
+                let alignof = match dialect {
+                    CDialect::Kernel(KernelTarget::Cuda) => "alignof",
+                    CDialect::Kernel(KernelTarget::OpenCL) => "__alignof__",
+                    _ => "_Alignof",
+                };
+                Ok(format!(
+                    "{}({})",
+                    alignof,
+                    ty.declare("", dialect, context)?
+                ))
+            }
+            OffsetOf(ty, field) => {
+                Self::check_layout_queries(dialect)?;
+                let has_field = match ty {
+                    CType::Struct { fields, .. } => fields.iter().any(|(name, _)| name == field),
+                    _ => false,
+                };
+                if !has_field {
+                    return Err(CodegenError::UnknownField {
+                        ty: ty.clone(),
+                        field: field.clone(),
+                    });
+                }
+                // OpenCL C has no `<stddef.h>`
+                let offsetof = if dialect == CDialect::Kernel(KernelTarget::OpenCL) {
+                    "__builtin_offsetof"
+                } else {
+                    context.include("stddef.h");
+                    "offsetof"
+                };
+                let ty = ty.declare("", dialect, context)?;
+                Ok(format!("{}({}, {})", offsetof, ty, field))
+            }
+            Ternary(cond, then, otherwise) => {
+                let cond = cond.operand(precedence::LOGICAL_OR, dialect, context)?;
+                let then = then.to_c(dialect, context)?;
+                let otherwise = otherwise.operand(precedence::CONDITIONAL, dialect, context)?;
+                Ok(format!("{} ? {} : {}", cond, then, otherwise))
+            }
+            AutoDiff(_op, _args) => Err(CodegenError::UnsupportedFeature {
+                feature: "automatic differentiation",
+                dialect,
+            }),
         }
     }
 }
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_int128_literals() {
+        let context = Context::standard("m".to_string());
+        let to_c = |literal: CLiteral| literal.to_c(CDialect::Standard, &context).unwrap();
+        assert_eq!(
+            to_c(CLiteral::UInt128(u128::MAX)),
+            "(((unsigned __int128)0xffffffffffffffffull << 64) | 0xffffffffffffffffull)"
+        );
+        assert_eq!(
+            to_c(CLiteral::Int128(5)),
+            "((__int128)((unsigned __int128)0x5ull))"
+        );
+        assert_eq!(
+            to_c(CLiteral::Int128(i128::MIN)),
+            "(-((__int128)(((unsigned __int128)0x7fffffffffffffffull << 64) | 0xffffffffffffffffull)) - 1)"
+        );
+    }
+
+    #[test]
+    fn test_minimal_parentheses() {
+        let context = Context::standard("m".to_string());
+        let var = |name: &str| Box::new(CValue::Variable(name.to_string()));
+        let bin = |op, lhs, rhs| Box::new(CValue::BinOp(op, lhs, rhs));
+        let to_c = |value: Box<CValue>| value.to_c(CDialect::Standard, &context).unwrap();
+        // a * (b + c) keeps the parentheses, (a * b) + c drops them
+        assert_eq!(
+            to_c(bin(
+                BinaryOp::Mul,
+                var("a"),
+                bin(BinaryOp::Add, var("b"), var("c"))
+            )),
+            "m_MM_a * (m_MM_b + m_MM_c)"
+        );
+        assert_eq!(
+            to_c(bin(
+                BinaryOp::Add,
+                bin(BinaryOp::Mul, var("a"), var("b")),
+                var("c")
+            )),
+            "m_MM_a * m_MM_b + m_MM_c"
+        );
+        // left associative: a - (b - c) but a - b - c
+        assert_eq!(
+            to_c(bin(
+                BinaryOp::Sub,
+                var("a"),
+                bin(BinaryOp::Sub, var("b"), var("c"))
+            )),
+            "m_MM_a - (m_MM_b - m_MM_c)"
+        );
+        assert_eq!(
+            to_c(bin(
+                BinaryOp::Sub,
+                bin(BinaryOp::Sub, var("a"), var("b")),
+                var("c")
+            )),
+            "m_MM_a - m_MM_b - m_MM_c"
+        );
+        // right associative: a = b = c
+        assert_eq!(
+            to_c(bin(
+                BinaryOp::Assign,
+                var("a"),
+                bin(BinaryOp::Assign, var("b"), var("c"))
+            )),
+            "m_MM_a = m_MM_b = m_MM_c"
+        );
+        // parentheses gcc's -Wparentheses asks for
+        assert_eq!(
+            to_c(bin(
+                BinaryOp::Or,
+                var("a"),
+                bin(BinaryOp::And, var("b"), var("c"))
+            )),
+            "m_MM_a || (m_MM_b && m_MM_c)"
+        );
+        assert_eq!(
+            to_c(bin(
+                BinaryOp::BitAnd,
+                var("a"),
+                bin(BinaryOp::Eq, var("b"), var("c"))
+            )),
+            "m_MM_a & (m_MM_b == m_MM_c)"
+        );
+        assert_eq!(
+            to_c(Box::new(CValue::UnaryOp(
+                UnaryOp::Neg,
+                Box::new(CValue::UnaryOp(UnaryOp::Neg, var("a")))
+            ))),
+            "-(-m_MM_a)"
+        );
+        assert_eq!(
+            to_c(Box::new(CValue::Dereference(Box::new(CValue::UnaryOp(
+                UnaryOp::PostInc,
+                var("p")
+            ))))),
+            "*m_MM_p++"
+        );
+        assert_eq!(
+            to_c(Box::new(CValue::MemberAccess(
+                Box::new(CValue::Dereference(var("p"))),
+                "x".to_string()
+            ))),
+            "(*m_MM_p).x"
+        );
+        assert!(
+            CValue::Reference(var("a"))
+                .to_c(CDialect::Kernel(KernelTarget::GLSL), &context)
+                .is_err()
+        );
+    }
+
+    #[test]
+    fn test_layout_and_ternary() {
+        let context = Context::standard("m".to_string());
+        let to_c = |value: CValue| value.to_c(CDialect::Standard, &context);
+        let point = CType::Struct {
+            name: Some("point".to_string()),
+            repr: None,
+            fields: vec![("x".to_string(), CType::F32), ("y".to_string(), CType::F32)],
+        };
+        let var = |name: &str| Box::new(CValue::Variable(name.to_string()));
+        assert_eq!(
+            to_c(CValue::BinOp(
+                BinaryOp::Mul,
+                Box::new(CValue::SizeOf(point.clone())),
+                var("n")
+            ))
+            .unwrap(),
+            "sizeof(struct m_MM_point) * m_MM_n"
+        );
+        assert_eq!(
+            to_c(CValue::OffsetOf(point.clone(), "y".to_string())).unwrap(),
+            "offsetof(struct m_MM_point, y)"
+        );
+        assert_eq!(
+            to_c(CValue::OffsetOf(point.clone(), "z".to_string())),
+            Err(CodegenError::UnknownField {
+                ty: point,
+                field: "z".to_string()
+            })
+        );
+        assert_eq!(
+            to_c(CValue::AlignOf(CType::F64)).unwrap(),
+            "_Alignof(double)"
+        );
+        // the cast applies to the whole sum
+        assert_eq!(
+            to_c(CValue::Cast(
+                CType::Pointer {
+                    ty: Box::new(CType::U8)
+                },
+                Box::new(CValue::BinOp(BinaryOp::Add, var("p"), var("n")))
+            ))
+            .unwrap(),
+            "(uint8_t *)(m_MM_p + m_MM_n)"
+        );
+        // conditionals nest to the right without parentheses
+        assert_eq!(
+            to_c(CValue::Ternary(
+                Box::new(CValue::BinOp(BinaryOp::Lt, var("a"), var("b"))),
+                var("a"),
+                Box::new(CValue::Ternary(var("c"), var("b"), var("c")))
+            ))
+            .unwrap(),
+            "m_MM_a < m_MM_b ? m_MM_a : m_MM_c ? m_MM_b : m_MM_c"
+        );
+        assert!(
+            context
+                .c_file
+                .lock()
+                .unwrap()
+                .includes
+                .contains("<stddef.h>")
+        );
+        assert_eq!(
+            CValue::Cast(CType::F32, var("i"))
+                .to_c(CDialect::Kernel(KernelTarget::GLSL), &context)
+                .unwrap(),
+            "float(m_MM_i)"
+        );
+    }
+
+    #[test]
+    fn test_string_escapes() {
+        let context = Context::standard("m".to_string());
+        let to_c = |literal: CLiteral| literal.to_c(CDialect::Standard, &context);
+        assert_eq!(
+            to_c(CLiteral::CString(
+                "say \"hi\"\\\n??=\0".to_string() + "1",
+                StringPrefix::None
+            ))
+            .unwrap(),
+            r#""say \"hi\"\\\n?\?=\0001""#
+        );
+        assert_eq!(
+            to_c(CLiteral::CString("é€😀".to_string(), StringPrefix::Utf8)).unwrap(),
+            r#"u8"\303\251\342\202\254\360\237\230\200""#
+        );
+        assert_eq!(
+            to_c(CLiteral::CString(
+                "é😀\u{85}".to_string(),
+                StringPrefix::Utf32
+            ))
+            .unwrap(),
+            r#"U"\u00e9\U0001f600\205""#
+        );
+        assert_eq!(
+            to_c(CLiteral::CChar('\'', StringPrefix::None)).unwrap(),
+            r"'\''"
+        );
+        assert_eq!(
+            to_c(CLiteral::CChar('€', StringPrefix::Utf16)).unwrap(),
+            r"u'\u20ac'"
+        );
+        assert_eq!(
+            to_c(CLiteral::CChar('😀', StringPrefix::Utf16)),
+            Err(CodegenError::InvalidCharLiteral {
+                value: '😀',
+                prefix: StringPrefix::Utf16
+            })
+        );
+        assert_eq!(
+            to_c(CLiteral::Bytes(vec![0, 0xff, b'a', b'7'])).unwrap(),
+            r#""\000\377a7""#
+        );
+        assert_eq!(
+            to_c(CLiteral::CChar('a', StringPrefix::Utf8)).unwrap(),
+            "'a'"
+        );
+        // pieces of at most 2048 bytes with the terminator
+        let long = to_c(CLiteral::CString("a".repeat(5000), StringPrefix::None)).unwrap();
+        assert_eq!(
+            long,
+            format!(
+                "\"{}\"\n\"{}\"\n\"{}\"",
+                "a".repeat(2047),
+                "a".repeat(2047),
+                "a".repeat(906)
+            )
+        );
+        let wide = to_c(CLiteral::CString("a".repeat(600), StringPrefix::Wide)).unwrap();
+        assert_eq!(
+            wide,
+            format!("L\"{}\"\nL\"{}\"", "a".repeat(511), "a".repeat(89))
+        );
+    }
+
+    #[test]
+    fn test_float_literals() {
+        let context = Context::standard("m".to_string());
+        let to_c = |literal: CLiteral| literal.to_c(CDialect::Standard, &context).unwrap();
+        assert_eq!(to_c(CLiteral::Float(1.0, FloatSuffix::None)), "1.0");
+        assert_eq!(to_c(CLiteral::Float(0.1, FloatSuffix::F32)), "0.1f");
+        assert_eq!(to_c(CLiteral::Float(0.1, FloatSuffix::F64)), "0.1");
+        assert_eq!(to_c(CLiteral::Float(1e300, FloatSuffix::None)), "1e300");
+        assert_eq!(to_c(CLiteral::HexFloat(3.0, FloatSuffix::None)), "0x1.8p+1");
+        assert_eq!(
+            to_c(CLiteral::HexFloat(0.1, FloatSuffix::F32)),
+            "0x1.99999ap-4f"
+        );
+        assert_eq!(
+            to_c(CLiteral::HexFloat(f64::from_bits(1), FloatSuffix::None)),
+            "0x0.0000000000001p-1022"
+        );
+        assert_eq!(to_c(CLiteral::HexFloat(-0.0, FloatSuffix::None)), "-0x0p+0");
+        assert!(!context.c_file.lock().unwrap().includes.contains("<math.h>"));
+        assert_eq!(
+            to_c(CLiteral::Float(f64::NEG_INFINITY, FloatSuffix::None)),
+            "-INFINITY"
+        );
+        assert_eq!(to_c(CLiteral::Float(f64::NAN, FloatSuffix::F32)), "NAN");
+        assert!(context.c_file.lock().unwrap().includes.contains("<math.h>"));
+        let glsl = |literal: CLiteral| literal.to_c(CDialect::Kernel(KernelTarget::GLSL), &context);
+        assert_eq!(
+            glsl(CLiteral::Float(1.5, FloatSuffix::F64)).unwrap(),
+            "1.5lf"
+        );
+        assert_eq!(
+            glsl(CLiteral::Float(f64::INFINITY, FloatSuffix::F32)).unwrap(),
+            "uintBitsToFloat(0x7f800000u)"
+        );
+        assert!(glsl(CLiteral::HexFloat(1.5, FloatSuffix::None)).is_err());
+        // finite values that overflow the literal type
+        let literal = |literal: CLiteral| literal.to_c(CDialect::Standard, &context);
+        assert_eq!(
+            literal(CLiteral::Float(1e300, FloatSuffix::F32)),
+            Err(CodegenError::FloatOutOfRange {
+                value: "1e300".to_string(),
+                ty: CType::F32
+            })
+        );
+        assert!(literal(CLiteral::HexFloat(-1e39, FloatSuffix::F32)).is_err());
+        assert!(literal(CLiteral::Float(65520.0, FloatSuffix::F16)).is_err());
+        assert_eq!(
+            to_c(CLiteral::Float(f32::MAX as f64, FloatSuffix::F32)),
+            "3.4028235e38f"
+        );
+    }
+
+    #[test]
+    fn test_int_literals() {
+        let context = Context::standard("m".to_string());
+        let int = |v, suffix, radix| CLiteral::Int(v, suffix, radix);
+        let typed = |v, ty| int(v, IntegerSuffix::Typed(ty), Radix::Decimal);
+        let to_c = |literal: CLiteral| literal.to_c(CDialect::Standard, &context);
+        assert_eq!(to_c(typed(-5, CType::I32)).unwrap(), "-5");
+        assert_eq!(to_c(typed(7, CType::U8)).unwrap(), "7");
+        assert_eq!(to_c(typed(7, CType::U32)).unwrap(), "7u");
+        assert_eq!(
+            to_c(typed(i32::MIN as i128, CType::I32)).unwrap(),
+            "-2147483647 - 1"
+        );
+        assert_eq!(
+            to_c(typed(i64::MIN as i128, CType::I64)).unwrap(),
+            "-9223372036854775807ll - 1"
+        );
+        assert_eq!(
+            to_c(typed(u64::MAX as i128, CType::U64)).unwrap(),
+            "18446744073709551615ull"
+        );
+        assert_eq!(
+            to_c(typed(1 << 40, CType::I32)).err(),
+            Some(CodegenError::IntegerOutOfRange {
+                value: 1 << 40,
+                ty: CType::I32
+            })
+        );
+        assert!(to_c(typed(-1, CType::U64)).is_err());
+        assert!(to_c(int(1 << 64, IntegerSuffix::ULL, Radix::Decimal)).is_err());
+        assert_eq!(
+            to_c(int(255, IntegerSuffix::U, Radix::Hex)).unwrap(),
+            "0xffu"
+        );
+        assert_eq!(
+            to_c(int(8, IntegerSuffix::None, Radix::Octal)).unwrap(),
+            "010"
+        );
+        assert_eq!(
+            to_c(int(0, IntegerSuffix::None, Radix::Octal)).unwrap(),
+            "0"
+        );
+        assert_eq!(
+            to_c(int(5, IntegerSuffix::None, Radix::Binary)).unwrap(),
+            "0b101"
+        );
+        assert_eq!(
+            to_c(int(i64::MIN as i128, IntegerSuffix::LL, Radix::Hex)).unwrap(),
+            "-0x7fffffffffffffffll - 1"
+        );
+        // the range follows the suffix and, without one, the base
+        let out_of_range = |v, suffix, radix, ty| {
+            assert_eq!(
+                to_c(int(v, suffix, radix)).err(),
+                Some(CodegenError::IntegerOutOfRange { value: v, ty })
+            )
+        };
+        out_of_range(-1, IntegerSuffix::U, Radix::Decimal, CType::U64);
+        out_of_range(1 << 63, IntegerSuffix::LL, Radix::Hex, CType::I64);
+        out_of_range(1 << 63, IntegerSuffix::None, Radix::Decimal, CType::I64);
+        out_of_range(1 << 64, IntegerSuffix::None, Radix::Hex, CType::U64);
+        assert_eq!(
+            to_c(int(u64::MAX as i128, IntegerSuffix::None, Radix::Hex)).unwrap(),
+            "0xffffffffffffffff"
+        );
+        assert_eq!(
+            int(1 << 32, IntegerSuffix::U, Radix::Decimal)
+                .to_c(CDialect::Kernel(KernelTarget::GLSL), &context)
+                .err(),
+            Some(CodegenError::IntegerOutOfRange {
+                value: 1 << 32,
+                ty: CType::U32
+            })
+        );
+
+        // the minimum is a subtraction, a negative literal a unary minus
+        let sub = CValue::BinOp(
+            BinaryOp::Sub,
+            Box::new(CValue::Variable("x".to_string())),
+            Box::new(CValue::Literal(typed(i32::MIN as i128, CType::I32))),
+        );
+        assert_eq!(
+            sub.to_c(CDialect::Standard, &context).unwrap(),
+            "m_MM_x - (-2147483647 - 1)"
+        );
+        let mul = CValue::BinOp(
+            BinaryOp::Mul,
+            Box::new(CValue::Variable("x".to_string())),
+            Box::new(CValue::Literal(typed(-5, CType::I32))),
+        );
+        assert_eq!(
+            mul.to_c(CDialect::Standard, &context).unwrap(),
+            "m_MM_x * -5"
+        );
+
+        let lp64 = Context::standard("m".to_string()).with_data_model(DataModel::LP64);
+        assert_eq!(
+            typed(i64::MIN as i128, CType::I64)
+                .to_c(CDialect::Standard, &lp64)
+                .unwrap(),
+            "-9223372036854775807l - 1"
+        );
+        let opencl = CDialect::Kernel(KernelTarget::OpenCL);
+        assert_eq!(typed(1, CType::U64).to_c(opencl, &context).unwrap(), "1ul");
+        assert!(
+            int(5, IntegerSuffix::None, Radix::Binary)
+                .to_c(opencl, &context)
+                .is_err()
+        );
+        let glsl = CDialect::Kernel(KernelTarget::GLSL);
+        assert_eq!(typed(3, CType::U32).to_c(glsl, &context).unwrap(), "3u");
+        assert!(typed(3, CType::I64).to_c(glsl, &context).is_err());
+    }
+}
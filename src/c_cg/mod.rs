@@ -1,23 +1,39 @@
 pub mod c_arch;
+pub mod c_cuda;
+pub mod c_error;
 pub mod c_file;
+pub mod c_glsl;
+pub mod c_mpi;
+pub mod c_openacc;
+pub mod c_opencl;
+pub mod c_openmp;
 pub mod c_stmt;
 pub mod c_type;
 pub mod c_value;
 
+use c_error::CResult;
 use c_stmt::Context;
 
 pub trait ToC {
-    fn to_c(&self, dialect: CDialect, context: &Context) -> Option<String>;
+    fn to_c(&self, dialect: CDialect, context: &Context) -> CResult<String>;
 }
 
-#[derive(Debug, Clone, Copy, PartialEq)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
 pub enum CDialect {
     // glsl, opencl/cuda host, etc.
-    Kernel,
+    Kernel(KernelTarget),
 
     // openmp, openacc, mpi
     Parallel,
 
     // standard c11 memory model C language
+    #[default]
     Standard,
 }
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum KernelTarget {
+    GLSL,
+    OpenCL,
+    Cuda,
+}
@@ -17,6 +17,27 @@ pub fn string_to_escape_to_c_ansi_id(module: &str, s: &str) -> String {
     format!("{}_MM_{}", module, s)
 }
 
+/// GLSL reserves identifiers containing `__`, so instead of `_X3A_` a
+/// character is escaped as `Z3Az` and `Z` itself as `ZZ`, leaving `_` to
+/// the `_MM_` separator
+fn _string_to_escape_to_glsl_id(s: &str) -> String {
+    let mut result = String::new();
+    for c in s.chars() {
+        match c {
+            'Z' => result.push_str("ZZ"),
+            'a'..='z' | 'A'..='Z' | '0'..='9' => result.push(c),
+            _ => result.push_str(&format!("Z{:X}z", c as u32)),
+        }
+    }
+    result
+}
+
+pub fn string_to_escape_to_glsl_id(module: &str, s: &str) -> String {
+    let module = _string_to_escape_to_glsl_id(module);
+    let s = _string_to_escape_to_glsl_id(s);
+    format!("{}_MM_{}", module, s)
+}
+
 pub fn _string_from_escape_to_c_ansi_id(s: &str) -> String {
     let mut result = String::new();
     let mut chars = s.chars();
@@ -54,7 +75,7 @@ pub fn _string_from_escape_to_c_ansi_id(s: &str) -> String {
 }
 
 pub fn string_from_escape_to_c_ansi_id(s: &str) -> (String, String) {
-    let mut m_s = s.split("_MM_").map(|s| _string_from_escape_to_c_ansi_id(s));
+    let mut m_s = s.split("_MM_").map(_string_from_escape_to_c_ansi_id);
     let m = m_s.next().unwrap();
     let s = m_s.next().unwrap();
     (m, s)
@@ -70,8 +91,13 @@ pub fn format_to_escape_replace(module: &str, mut code: String) -> String {
     code
 }
 
+/// unescaped temporary variable, suitable as a `Context` variable
+pub fn get_temp_variable() -> String {
+    format!("_tmp_{}", uuid::Uuid::now_v7())
+}
+
 pub fn get_temp_variable_name(module: &str) -> String {
-    string_to_escape_to_c_ansi_id(module, &format!("_tmp_{}", uuid::Uuid::now_v7()))
+    string_to_escape_to_c_ansi_id(module, &get_temp_variable())
 }
 
 #[cfg(test)]
@@ -90,14 +116,22 @@ mod test {
         assert_eq!(_string_to_escape_to_c_ansi_id("🈶🍤"), "_X1F236__X1F364_");
         assert_eq!(
             _string_to_escape_to_c_ansi_id("#:lam1-x"),
-            "_X23__X003A_lam1_X2D_x"
+            "_X23__X3A_lam1_X2D_x"
         );
         assert_eq!(
             _string_to_escape_to_c_ansi_id("#:lam1-y"),
-            "_X23__X003A_lam1_X2D_y"
+            "_X23__X3A_lam1_X2D_y"
         );
     }
 
+    #[test]
+    fn test_string_to_escape_to_glsl_id() {
+        assert_eq!(_string_to_escape_to_glsl_id("abc"), "abc");
+        assert_eq!(_string_to_escape_to_glsl_id("a::b"), "aZ3AzZ3Azb");
+        assert_eq!(_string_to_escape_to_glsl_id("Z3Az"), "ZZ3Az");
+        assert_eq!(string_to_escape_to_glsl_id("m", "_tmp"), "m_MM_Z5Fztmp");
+    }
+
     #[test]
     fn test_string_from_escape_to_c_ansi_id() {
         assert_eq!(_string_from_escape_to_c_ansi_id("abc"), "abc");